@@ -1,16 +1,44 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::io::{self, Write};
 use std::env;
+use std::path::{Path, PathBuf};
 
 mod config;
+mod crypto;
+mod export;
+mod generator;
+mod import;
+mod kdbx;
+mod migration;
+mod otp;
+mod pass;
+mod recovery;
+mod strength;
 mod vault;
 mod clipboard;
 mod search;
+mod query;
 
-use vault::{VaultManager, PasswordEntry};
+use search::{SearchEngine, SearchResult};
+use vault::{VaultManager, PasswordEntry, UsageKind};
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // `--vault <path>` is accepted anywhere on the command line
+    let vault_override = match args.iter().position(|arg| arg == "--vault") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(PathBuf::from(path))
+        }
+        Some(_) => {
+            eprintln!("Error: --vault requires a path");
+            return Ok(());
+        }
+        None => None,
+    };
+    let config = config::Config::load()?.with_vault_override(vault_override.as_deref());
     
     if args.len() < 2 {
         print_usage();
@@ -23,10 +51,10 @@ fn main() -> Result<()> {
         "add" => {
             if args.len() < 3 {
                 eprintln!("Error: Please provide a name for the entry");
-                eprintln!("Usage: passman add <name>");
+                eprintln!("Usage: passman add <name> [--generate] [--username <user>] [--url <url>] [--tags <a,b>] [--notes <text>] [--otp <secret|uri>]");
                 return Ok(());
             }
-            add_entry(&args[2])?;
+            add_entry(&config, &args[2], &args[3..])?;
         }
         "delete" => {
             if args.len() < 3 {
@@ -34,18 +62,74 @@ fn main() -> Result<()> {
                 eprintln!("Usage: passman delete <name>");
                 return Ok(());
             }
-            delete_entry(&args[2])?;
+            delete_entry(&config, &args[2])?;
         }
         "list" => {
-            list_entries()?;
+            list_entries(&config)?;
         }
         "search" => {
             if args.len() < 3 {
                 eprintln!("Error: Please provide a search term");
-                eprintln!("Usage: passman search <term>");
+                eprintln!("Usage: passman search <query>");
+                return Ok(());
+            }
+            search_entries(&config, &args[2..].join(" "))?;
+        }
+        "generate" => {
+            generate(&config, &args[2..])?;
+        }
+        "otp" => {
+            if args.len() < 3 {
+                eprintln!("Error: Please provide the name of the entry");
+                eprintln!("Usage: passman otp <name> [--set <secret|uri> | --remove]");
+                return Ok(());
+            }
+            one_time_code(&config, &args[2], &args[3..])?;
+        }
+        "import" => {
+            if args.len() < 3 {
+                eprintln!("Error: Please provide the file to import");
+                eprintln!("Usage: passman import <file> [--format <format>] [--duplicates skip|rename|overwrite] [--dry-run]");
+                return Ok(());
+            }
+            import_entries(&config, Path::new(&args[2]), &args[3..])?;
+        }
+        "export" => {
+            if args.len() < 3 {
+                eprintln!("Error: Please provide the file to export to");
+                eprintln!("Usage: passman export <file> [--format json|csv|dotenv|sheet|kdbx|pass] [--entries <a,b>] [--tag <tag>]");
                 return Ok(());
             }
-            search_entries(&args[2])?;
+            export_entries(&config, Path::new(&args[2]), &args[3..])?;
+        }
+        "recover" => {
+            recover_vault(&config)?;
+        }
+        "backups" => {
+            match args.get(2).map(String::as_str) {
+                Some("list") | None => list_backups(&config)?,
+                Some("restore") => {
+                    let Some(number) = args.get(3).and_then(|n| n.parse::<usize>().ok()) else {
+                        eprintln!("Error: Please provide the number of the backup to restore");
+                        eprintln!("Usage: passman backups restore <number>");
+                        return Ok(());
+                    };
+                    restore_backup(&config, number)?;
+                }
+                Some(other) => {
+                    eprintln!("Error: Unknown backups command '{}'", other);
+                    eprintln!("Usage: passman backups [list | restore <number>]");
+                }
+            }
+        }
+        "doctor" => {
+            match args.get(2).map(String::as_str) {
+                Some("clipboard") | None => doctor_clipboard(&config),
+                Some(other) => {
+                    eprintln!("Error: Unknown doctor check '{}'", other);
+                    eprintln!("Usage: passman doctor [clipboard]");
+                }
+            }
         }
         "help" | "--help" | "-h" => {
             print_usage();
@@ -62,26 +146,309 @@ fn main() -> Result<()> {
 fn print_usage() {
     println!("Keytui CLI - Password Manager");
     println!();
-    println!("Usage: passman <command> [options]");
+    println!("Usage: passman [--vault <path>] <command> [options]");
     println!();
     println!("Commands:");
     println!("  add <name>     Add a new password entry");
+    println!("                   --username <user>  Account username");
+    println!("                   --url <url>        Website or service URL");
+    println!("                   --tags <a,b>       Comma separated tags");
+    println!("                   --notes <text>     Free-form notes");
+    println!("                   --otp <secret|uri> Base32 secret or otpauth:// URI for one-time codes");
+    println!("                   --generate         Generate the password (see generate options)");
+    println!("  generate       Print a random password or passphrase");
+    println!("                   --length <n>       Number of characters");
+    println!("                   --no-lowercase, --no-uppercase, --no-digits, --no-symbols");
+    println!("                                      Leave out a character class");
+    println!("                   --exclude-ambiguous  Avoid look-alikes such as l, 1, O and 0");
+    println!("                   --words <n>        Generate a passphrase of n words instead");
+    println!("                   --passphrase       Generate a passphrase with the configured settings");
+    println!("                   --separator <sep>  Text placed between passphrase words");
+    println!("                   --capitalize       Capitalize each passphrase word");
+    println!("                   --digit            Add a random digit to one passphrase word");
+    println!("                   --password         Generate a password even if passphrases are the default");
+    println!("  otp <name>     Print the current one-time code of an entry");
+    println!("                   --set <secret|uri> Attach a base32 secret or otpauth:// URI");
+    println!("                   --remove           Remove the one-time code generator");
     println!("  delete <name>  Delete a password entry");
     println!("  list           List all password entries");
-    println!("  search <term>  Search for password entries");
+    println!("  search <query> Search for password entries, best matches first");
+    println!("                   git                fuzzy match name, username, URL or tags");
+    println!("                   tag:work           only one field: name, user, url, tag,");
+    println!("                                      folder or notes");
+    println!("                   -tag:archived      leave out entries that match");
+    println!("                   \"two words\" 'git  match the text exactly");
+    println!("                   ^prefix suffix$    anchor to the start or end of the field");
+    println!("  import <file>  Import entries from another password manager's export");
+    println!("                   --format <format>  chrome, firefox, bitwarden, lastpass, 1password, kdbx");
+    println!("                                      or pass (detected from the file when left out)");
+    println!("                   --duplicates <how> skip (default), rename or overwrite entries whose");
+    println!("                                      name, username and URL are already in the vault");
+    println!("                   --dry-run          Show what would be imported without saving");
+    println!("  export <file>  Write the vault to a file (format taken from the file name)");
+    println!("                   --format <format>  json      encrypted with its own password");
+    println!("                                      csv       plain text, needs --unsafe-plaintext");
+    println!("                                      dotenv    NAME_PASSWORD=... lines for --entries/--tag");
+    println!("                                      sheet     printable text, or HTML for .html files");
+    println!("                                      kdbx      KeePass KDBX 4 database");
+    println!("                                      pass      pass directory layout, one GPG file per entry");
+    println!("                   --entries <a,b>    Only export these entries");
+    println!("                   --tag <tag>        Only export entries with this tag");
+    println!("                   --cipher <cipher>  chacha20 (default) or aes, for kdbx");
+    println!("                   --recipient <key>  GPG key to encrypt for, for pass (defaults to");
+    println!("                                      the store's .gpg-id)");
+    println!("  recover        Restore a backup or salvage entries from a corrupt vault");
+    println!("  backups list   List vault backups, newest first");
+    println!("  backups restore <number>");
+    println!("                 Roll the vault back to a backup from the list");
+    println!("  doctor clipboard");
+    println!("                 Show which clipboard backend copies will use, and why others can't");
     println!("  help           Show this help message");
     println!();
+    println!("The vault location is taken from --vault, then the {} environment", config::VAULT_ENV_VAR);
+    println!("variable, then vault_path in config.toml.");
+    println!();
     println!("Examples:");
     println!("  passman add gmail");
+    println!("  passman add github --username alice --url github.com --tags work,dev");
+    println!("  passman add gitlab --generate --length 32");
+    println!("  passman generate --length 16 --no-symbols");
+    println!("  passman generate --words 7 --separator . --capitalize");
+    println!("  passman otp github --set 'otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP'");
+    println!("  passman otp github");
     println!("  passman delete gmail");
     println!("  passman list");
     println!("  passman search gmail");
+    println!("  passman search tag:work -tag:archived 'git");
+    println!("  passman import bitwarden_export.json --dry-run");
+    println!("  passman import passwords.csv --duplicates rename");
+    println!("  passman import keepass.kdbx --duplicates rename");
+    println!("  passman import ~/.password-store");
+    println!("  passman export backup.json");
+    println!("  passman export passman.kdbx");
+    println!("  passman export .env --entries github,aws");
+    println!("  passman export emergency.html");
+    println!("  passman export ~/.password-store --format pass --recipient alice@example.com");
+    println!("  passman backups restore 1");
 }
 
-fn add_entry(name: &str) -> Result<()> {
+fn load_vault_manager(config: &config::Config) -> Result<VaultManager> {
+    let mut vault_manager = VaultManager::new(config)?;
+    if let Some(home) = dirs::home_dir().filter(|_| !config.vault_overridden) {
+        vault_manager.relocate_legacy_vault(&home)?;
+    }
+    if let Some(legacy_path) = vault_manager.relocated_from() {
+        println!(
+            "📦 Moved vault from {} to {}",
+            legacy_path.display(),
+            vault_manager.path().display()
+        );
+    }
+    Ok(vault_manager)
+}
+
+fn open_vault(config: &config::Config) -> Result<VaultManager> {
+    let mut vault_manager = load_vault_manager(config)?;
+    let password = prompt_master_password(&vault_manager)?;
+
+    match vault_manager.unlock(&password) {
+        Ok(Some(upgrade)) => {
+            println!(
+                "🔒 Vault upgraded from format v{} to v{} (backup saved to {:?})",
+                upgrade.from_version,
+                migration::CURRENT_FORMAT_VERSION,
+                upgrade.backup_path
+            );
+        }
+        Ok(None) => {}
+        Err(e) if vault_manager.corruption().is_some() => {
+            return Err(anyhow::anyhow!(
+                "{}\nRun `passman recover` to restore a backup or salvage entries",
+                e
+            ));
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(vault_manager)
+}
+
+fn prompt_master_password(vault_manager: &VaultManager) -> Result<String> {
+    let password = if vault_manager.exists() {
+        rpassword::prompt_password("Master password: ")?
+    } else {
+        println!("No vault found, creating a new one.");
+        let password = rpassword::prompt_password("New master password: ")?;
+        let confirm = rpassword::prompt_password("Confirm master password: ")?;
+        if password != confirm {
+            return Err(anyhow::anyhow!("Passwords did not match"));
+        }
+        password
+    };
+
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("Master password cannot be empty"));
+    }
+    Ok(password)
+}
+
+fn recover_vault(config: &config::Config) -> Result<()> {
+    let mut vault_manager = load_vault_manager(config)?;
+    if !vault_manager.exists() {
+        println!("No vault found, nothing to recover.");
+        return Ok(());
+    }
+
+    let password = prompt_master_password(&vault_manager)?;
+    if let Err(e) = vault_manager.unlock(&password) {
+        if vault_manager.corruption().is_none() {
+            return Err(e);
+        }
+    }
+
+    let Some(corruption) = vault_manager.corruption() else {
+        println!("✅ Vault is healthy, nothing to recover.");
+        return Ok(());
+    };
+
+    println!("⚠️  {}", corruption);
+    println!("The vault will not be written until it is recovered.");
+    println!();
+
+    let latest_backup = vault_manager.backups()?.into_iter().next();
+    let salvageable = vault_manager.salvageable_entries();
+    match &latest_backup {
+        Some(backup) => println!("  r  Restore the most recent backup ({})", backup.display()),
+        None => println!("  r  Restore the most recent backup (none found)"),
+    }
+    match vault_manager.salvage_source() {
+        Some(source) => println!(
+            "  s  Salvage {} readable entries from {} into a new vault",
+            salvageable.len(),
+            source.display()
+        ),
+        None => println!("  s  Salvage entries (unavailable: no backup opens with this password)"),
+    }
+    println!("  q  Quit without changing anything");
+    print!("Choice: ");
+    io::stdout().flush()?;
+
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+
+    match choice.trim() {
+        "r" => {
+            let Some(backup) = latest_backup else {
+                eprintln!("Error: No backup available");
+                return Ok(());
+            };
+            let preserved = vault_manager.restore_backup(&backup)?;
+            println!("✅ Restored {}", backup.display());
+            if let Some(preserved) = preserved {
+                println!("   Corrupt vault kept at {}", preserved.display());
+            }
+        }
+        "s" => {
+            let preserved = vault_manager.recover_salvaged_entries()?;
+            println!("✅ Recovered {} entries", salvageable.len());
+            println!("   Corrupt vault kept at {}", preserved.display());
+        }
+        _ => println!("Nothing changed."),
+    }
+
+    Ok(())
+}
+
+/// Prints the estimated strength of `password` and returns whether it meets
+/// `min_password_score` from the config.
+fn check_strength(config: &config::Config, password: &str, user_inputs: &[Option<&str>]) -> bool {
+    let user_inputs: Vec<&str> = user_inputs.iter().flatten().copied().collect();
+    let strength = strength::estimate(password, &user_inputs);
+    println!("💪 Strength: {}", strength.summary());
+    if let Some(warning) = strength.warning {
+        println!("   ⚠️  {}", warning);
+    }
+
+    if strength.score < config.min_password_score {
+        eprintln!(
+            "Error: Password scores {}/4, below the minimum of {} set in config.toml",
+            strength.score, config.min_password_score
+        );
+        return false;
+    }
+    true
+}
+
+/// Returns the value following `flag` in `args`, e.g. `--url github.com`.
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+/// Generates a password using the `[generator]` policy from the config, or a
+/// passphrase using the `[passphrase]` policy, adjusted by any generator
+/// flags in `options`.
+fn generate_secret(config: &config::Config, options: &[String]) -> Result<generator::Generated> {
+    let passphrase_flags = ["--words", "--passphrase", "--separator", "--capitalize", "--digit"];
+    let wants_passphrase = passphrase_flags.iter().any(|flag| has_flag(options, flag))
+        || (config.passphrase.default && !has_flag(options, "--password"));
+
+    if wants_passphrase {
+        let mut policy = config.passphrase.clone();
+        if let Some(words) = flag_value(options, "--words") {
+            policy.words = words
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid --words '{}'", words))?;
+        }
+        if let Some(separator) = flag_value(options, "--separator") {
+            policy.separator = separator;
+        }
+        policy.capitalize |= has_flag(options, "--capitalize");
+        policy.insert_digit |= has_flag(options, "--digit");
+        return generator::generate_passphrase(&policy);
+    }
+
+    let mut policy = config.generator.clone();
+    if let Some(length) = flag_value(options, "--length") {
+        policy.length = length
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid --length '{}'", length))?;
+    }
+    policy.lowercase &= !has_flag(options, "--no-lowercase");
+    policy.uppercase &= !has_flag(options, "--no-uppercase");
+    policy.digits &= !has_flag(options, "--no-digits");
+    policy.symbols &= !has_flag(options, "--no-symbols");
+    policy.exclude_ambiguous |= has_flag(options, "--exclude-ambiguous");
+
+    generator::generate_password(&policy)
+}
+
+fn generate(config: &config::Config, options: &[String]) -> Result<()> {
+    let generated = generate_secret(config, options)?;
+    println!("{}", generated.secret);
+    // On stderr so the secret alone can be piped elsewhere
+    eprintln!("📏 {:.0} bits of entropy", generated.entropy_bits);
+    Ok(())
+}
+
+fn add_entry(config: &config::Config, name: &str, options: &[String]) -> Result<()> {
+    // Generate up front so bad generator flags fail before the unlock prompt
+    let generated = if has_flag(options, "--generate") {
+        Some(generate_secret(config, options)?)
+    } else {
+        None
+    };
+    let otp = flag_value(options, "--otp")
+        .map(|otp| otp::OtpSecret::parse(&otp))
+        .transpose()?;
+
     // Load vault
-    let config = config::Config::load()?;
-    let mut vault_manager = VaultManager::new(&config)?;
+    let mut vault_manager = open_vault(config)?;
     
     // Check if entry already exists
     if vault_manager.get_all_entries()?.iter().any(|e| e.name == name) {
@@ -89,27 +456,38 @@ fn add_entry(name: &str) -> Result<()> {
         return Ok(());
     }
     
-    // Get password from user
-    print!("Enter password for '{}': ", name);
-    io::stdout().flush()?;
-    
-    let password = rpassword::read_password()?;
+    // Generate the password or get it from the user
+    let password = if let Some(generated) = generated {
+        println!(
+            "🎲 Generated password: {} ({:.0} bits of entropy)",
+            generated.secret, generated.entropy_bits
+        );
+        generated.secret
+    } else {
+        print!("Enter password for '{}': ", name);
+        io::stdout().flush()?;
+        rpassword::read_password()?
+    };
     if password.is_empty() {
         eprintln!("Error: Password cannot be empty");
         return Ok(());
     }
     
+    let username = flag_value(options, "--username");
+    let url = flag_value(options, "--url");
+    if !check_strength(config, &password, &[Some(name), username.as_deref(), url.as_deref()]) {
+        return Ok(());
+    }
+    
     // Create entry
-    let entry = PasswordEntry {
-        id: uuid::Uuid::new_v4().to_string(),
-        name: name.to_string(),
-        username: None,
-        password,
-        url: None,
-        tags: vec![],
-        created_at: chrono::Utc::now(),
-        updated_at: chrono::Utc::now(),
-    };
+    let mut entry = PasswordEntry::new(name, &password);
+    entry.username = username;
+    entry.url = url;
+    entry.tags = flag_value(options, "--tags")
+        .map(|tags| vault::parse_tags(&tags))
+        .unwrap_or_default();
+    entry.notes = flag_value(options, "--notes");
+    entry.otp = otp;
     
     // Add to vault
     vault_manager.add_entry(entry)?;
@@ -119,10 +497,263 @@ fn add_entry(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn delete_entry(name: &str) -> Result<()> {
+fn one_time_code(config: &config::Config, name: &str, options: &[String]) -> Result<()> {
+    let new_secret = flag_value(options, "--set")
+        .map(|otp| otp::OtpSecret::parse(&otp))
+        .transpose()?;
+
+    let mut vault_manager = open_vault(config)?;
+    let Some(mut entry) = vault_manager.get_all_entries()?.into_iter().find(|e| e.name == name) else {
+        eprintln!("Error: Entry '{}' not found", name);
+        return Ok(());
+    };
+
+    if has_flag(options, "--remove") {
+        entry.otp = None;
+        vault_manager.update_entry(entry)?;
+        vault_manager.save_entries()?;
+        println!("✅ One-time codes removed from '{}'", name);
+        return Ok(());
+    }
+    if let Some(secret) = new_secret {
+        entry.otp = Some(secret);
+    }
+
+    let Some(otp) = entry.otp.as_mut() else {
+        eprintln!("Error: Entry '{}' has no one-time code; add one with --set <secret|uri>", name);
+        return Ok(());
+    };
+    let code = otp.next_code()?;
+    let id = entry.id.clone();
+
+    // HOTP codes advance the counter, which has to be saved as well
+    if has_flag(options, "--set") || otp.is_hotp() {
+        vault_manager.update_entry(entry)?;
+    }
+    vault_manager.record_use(&id, UsageKind::View)?;
+    vault_manager.save_entries()?;
+
+    match code.remaining_seconds {
+        Some(seconds) => println!("🔑 {} (valid for {}s)", code.code, seconds),
+        None => println!("🔑 {}", code.code),
+    }
+    Ok(())
+}
+
+fn import_entries(config: &config::Config, path: &Path, options: &[String]) -> Result<()> {
+    // Read the export before asking for the master password so a bad file
+    // or flag fails straight away
+    let format = flag_value(options, "--format")
+        .map(|format| import::ImportFormat::from_name(&format))
+        .transpose()?;
+    let strategy = flag_value(options, "--duplicates")
+        .map(|strategy| import::DuplicateStrategy::from_name(&strategy))
+        .transpose()?
+        .unwrap_or(import::DuplicateStrategy::Skip);
+    let dry_run = has_flag(options, "--dry-run");
+    let imported = import::read_file(path, format, || {
+        Ok(rpassword::prompt_password(format!("Password for {}: ", path.display()))?)
+    })?;
+    println!("📥 Read {} entries from {} ({})", imported.entries.len(), path.display(), imported.format.label());
+
+    let mut vault_manager = open_vault(config)?;
+    let plan = import::plan(vault_manager.entries(), imported.entries, strategy);
+
+    let (mut added, mut overwritten, mut skipped) = (0, 0, 0);
+    for action in &plan {
+        match action {
+            import::PlannedAction::Add(entry) => {
+                added += 1;
+                println!("  + {}", entry.summary());
+            }
+            import::PlannedAction::Rename { entry, original_name } => {
+                added += 1;
+                println!("  + {} (renamed from '{}')", entry.summary(), original_name);
+            }
+            import::PlannedAction::Overwrite(entry) => {
+                overwritten += 1;
+                println!("  ~ {} (overwrites existing entry)", entry.summary());
+            }
+            import::PlannedAction::Skip(entry) => {
+                skipped += 1;
+                println!("  = {} (duplicate, skipped)", entry.summary());
+            }
+        }
+    }
+    for reason in &imported.skipped {
+        println!("  ! {}", reason);
+    }
+
+    let summary = format!(
+        "{} new, {} overwritten, {} duplicates skipped, {} warnings",
+        added,
+        overwritten,
+        skipped,
+        imported.skipped.len()
+    );
+    if dry_run {
+        println!("🔍 Dry run: {}. Nothing was saved.", summary);
+        return Ok(());
+    }
+
+    for action in plan {
+        match action {
+            import::PlannedAction::Add(entry) | import::PlannedAction::Rename { entry, .. } => {
+                vault_manager.add_entry(entry)?
+            }
+            import::PlannedAction::Overwrite(entry) => vault_manager.update_entry(entry)?,
+            import::PlannedAction::Skip(_) => {}
+        }
+    }
+    vault_manager.save_entries()?;
+    println!("✅ Import finished: {}", summary);
+    Ok(())
+}
+
+fn export_entries(config: &config::Config, path: &Path, options: &[String]) -> Result<()> {
+    let format = match flag_value(options, "--format") {
+        Some(format) => export::ExportFormat::from_name(&format)?,
+        None => export::ExportFormat::from_path(path)
+            .ok_or_else(|| anyhow::anyhow!("Could not tell the export format from the file name; pass --format"))?,
+    };
+    if format == export::ExportFormat::Csv && !has_flag(options, "--unsafe-plaintext") {
+        return Err(anyhow::anyhow!(
+            "CSV exports are not encrypted; add --unsafe-plaintext if you really want one"
+        ));
+    }
+
+    match format {
+        export::ExportFormat::Kdbx => export_kdbx(config, path, options),
+        export::ExportFormat::Pass => export_pass(config, path, options),
+        _ => export_file(config, path, format, options),
+    }
+}
+
+fn export_file(config: &config::Config, path: &Path, format: export::ExportFormat, options: &[String]) -> Result<()> {
+    // dotenv files are meant for a few credentials, never the whole vault
+    let selected_names = flag_value(options, "--entries").map(|names| vault::parse_tags(&names));
+    let selected_tag = flag_value(options, "--tag");
+    if format == export::ExportFormat::Dotenv && selected_names.is_none() && selected_tag.is_none() {
+        return Err(anyhow::anyhow!("Choose the entries for a dotenv file with --entries <a,b> or --tag <tag>"));
+    }
+
+    if path.exists() && !confirm(&format!("{} already exists. Overwrite it?", path.display()))? {
+        println!("Nothing exported.");
+        return Ok(());
+    }
+
+    let vault_manager = open_vault(config)?;
+    let mut entries: Vec<PasswordEntry> = vault_manager.entries().to_vec();
+    if let Some(names) = &selected_names {
+        if let Some(missing) = names.iter().find(|name| !entries.iter().any(|e| &e.name == *name)) {
+            return Err(anyhow::anyhow!("No entry named '{}'", missing));
+        }
+        entries.retain(|entry| names.contains(&entry.name));
+    }
+    if let Some(tag) = &selected_tag {
+        entries.retain(|entry| entry.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+    }
+    if entries.is_empty() {
+        println!("No entries to export.");
+        return Ok(());
+    }
+
+    if format.is_plaintext()
+        && !confirm(&format!(
+            "⚠️  This writes {} passwords to {} without encryption. Continue?",
+            entries.len(),
+            path.display()
+        ))?
+    {
+        println!("Nothing exported.");
+        return Ok(());
+    }
+
+    let contents = match format {
+        export::ExportFormat::Json => {
+            let password = prompt_export_password("Password for the export")?;
+            zeroize::Zeroizing::new(export::to_encrypted_json(&entries, &password)?)
+        }
+        export::ExportFormat::Csv => export::to_csv(&entries)?,
+        export::ExportFormat::Dotenv => export::to_dotenv(&entries),
+        export::ExportFormat::Sheet => {
+            let html = path
+                .extension()
+                .is_some_and(|ext| matches!(ext.to_string_lossy().to_lowercase().as_str(), "html" | "htm"));
+            export::to_sheet(&entries, html)
+        }
+        export::ExportFormat::Kdbx | export::ExportFormat::Pass => unreachable!("written by their own exporters"),
+    };
+    vault::write_private_file(path, contents.as_bytes()).with_context(|| format!("Could not write {}", path.display()))?;
+    println!("📤 Exported {} entries to {} ({})", entries.len(), path.display(), format.label());
+    Ok(())
+}
+
+/// Asks for a new password to protect an export with, twice.
+fn prompt_export_password(label: &str) -> Result<String> {
+    let password = rpassword::prompt_password(format!("{}: ", label))?;
+    let confirm = rpassword::prompt_password("Confirm password: ")?;
+    if password != confirm {
+        return Err(anyhow::anyhow!("Passwords did not match"));
+    }
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("The export password cannot be empty"));
+    }
+    Ok(password)
+}
+
+fn export_kdbx(config: &config::Config, path: &Path, options: &[String]) -> Result<()> {
+    let cipher = flag_value(options, "--cipher")
+        .map(|cipher| kdbx::KdbxCipher::from_name(&cipher))
+        .transpose()?
+        .unwrap_or(kdbx::KdbxCipher::ChaCha20);
+
+    if path.exists() && !confirm(&format!("{} already exists. Overwrite it?", path.display()))? {
+        println!("Nothing exported.");
+        return Ok(());
+    }
+
+    let vault_manager = open_vault(config)?;
+    let password = prompt_export_password("Password for the KeePass database")?;
+
+    let data = kdbx::write(vault_manager.entries(), &password, cipher)?;
+    vault::write_private_file(path, &data).with_context(|| format!("Could not write {}", path.display()))?;
+    println!("📤 Exported {} entries to {} (KeePass KDBX 4)", vault_manager.entries().len(), path.display());
+    Ok(())
+}
+
+fn export_pass(config: &config::Config, store: &Path, options: &[String]) -> Result<()> {
+    let recipients: Vec<String> = flag_value(options, "--recipient").into_iter().collect();
+    let has_entries = std::fs::read_dir(store).is_ok_and(|mut items| items.next().is_some());
+    if has_entries
+        && !confirm(&format!("{} is not empty. Replace entries with the same name?", store.display()))?
+    {
+        println!("Nothing exported.");
+        return Ok(());
+    }
+
+    let vault_manager = open_vault(config)?;
+    let mut skipped = Vec::new();
+    let written = pass::write_store(store, vault_manager.entries(), &recipients, &mut skipped)?;
+    for reason in &skipped {
+        println!("  ! {}", reason);
+    }
+    println!("📤 Exported {} entries to {} (password-store)", written, store.display());
+    Ok(())
+}
+
+/// Asks a yes/no question, defaulting to no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn delete_entry(config: &config::Config, name: &str) -> Result<()> {
     // Load vault
-    let config = config::Config::load()?;
-    let mut vault_manager = VaultManager::new(&config)?;
+    let mut vault_manager = open_vault(config)?;
     
     // Find entry by name
     let entries = vault_manager.get_all_entries()?;
@@ -142,10 +773,9 @@ fn delete_entry(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn list_entries() -> Result<()> {
+fn list_entries(config: &config::Config) -> Result<()> {
     // Load vault
-    let config = config::Config::load()?;
-    let vault_manager = VaultManager::new(&config)?;
+    let vault_manager = open_vault(config)?;
     
     let entries = vault_manager.get_all_entries()?;
     
@@ -158,35 +788,18 @@ fn list_entries() -> Result<()> {
     println!("=================");
     
     for entry in entries {
-        let username = entry.username.as_ref().map(|u| format!(" ({})", u)).unwrap_or_default();
-        let url = entry.url.as_ref().map(|u| format!(" - {}", u)).unwrap_or_default();
-        let tags = if !entry.tags.is_empty() {
-            format!(" [{}]", entry.tags.join(", "))
-        } else {
-            String::new()
-        };
-        
-        println!("• {}{}{}{}", entry.name, username, url, tags);
+        println!("• {}", entry.summary());
     }
     
     Ok(())
 }
 
-fn search_entries(term: &str) -> Result<()> {
+fn search_entries(config: &config::Config, term: &str) -> Result<()> {
     // Load vault
-    let config = config::Config::load()?;
-    let vault_manager = VaultManager::new(&config)?;
+    let vault_manager = open_vault(config)?;
     
     let entries = vault_manager.get_all_entries()?;
-    let matches: Vec<_> = entries
-        .iter()
-        .filter(|entry| {
-            entry.name.to_lowercase().contains(&term.to_lowercase()) ||
-            entry.username.as_ref().map_or(false, |u| u.to_lowercase().contains(&term.to_lowercase())) ||
-            entry.url.as_ref().map_or(false, |u| u.to_lowercase().contains(&term.to_lowercase())) ||
-            entry.tags.iter().any(|tag| tag.to_lowercase().contains(&term.to_lowercase()))
-        })
-        .collect();
+    let matches = SearchEngine::new(config.sort).search(&entries, vault_manager.usage(), term);
     
     if matches.is_empty() {
         println!("No entries found matching '{}'", term);
@@ -196,17 +809,89 @@ fn search_entries(term: &str) -> Result<()> {
     println!("Search results for '{}':", term);
     println!("==========================");
     
-    for entry in matches {
-        let username = entry.username.as_ref().map(|u| format!(" ({})", u)).unwrap_or_default();
-        let url = entry.url.as_ref().map(|u| format!(" - {}", u)).unwrap_or_default();
-        let tags = if !entry.tags.is_empty() {
-            format!(" [{}]", entry.tags.join(", "))
-        } else {
-            String::new()
-        };
-        
-        println!("• {}{}{}{}", entry.name, username, url, tags);
+    for SearchResult { entry, .. } in matches {
+        println!("• {}", entry.summary());
+        if let Some(notes) = &entry.notes {
+            println!("    {}", notes);
+        }
     }
     
     Ok(())
 }
+
+fn list_backups(config: &config::Config) -> Result<()> {
+    let vault_manager = load_vault_manager(config)?;
+    let backups = vault_manager.backups()?;
+
+    if backups.is_empty() {
+        println!("No backups found.");
+        return Ok(());
+    }
+
+    println!("Vault backups (newest first, keeping {} generations):", vault_manager.backup_count());
+    println!("=====================================================");
+
+    for (i, backup) in backups.iter().enumerate() {
+        let modified = std::fs::metadata(backup)
+            .and_then(|metadata| metadata.modified())
+            .map(|time| chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        println!("{:>3}. {}  {}", i + 1, modified, backup.display());
+    }
+
+    Ok(())
+}
+
+fn doctor_clipboard(config: &config::Config) {
+    let manager = clipboard::ClipboardManager::new(config);
+
+    println!("Clipboard backends, in the order they are tried:");
+    println!("================================================");
+    for (name, backend) in manager.backends() {
+        match backend.and_then(|backend| backend.available()) {
+            Ok(()) => println!("  ✅ {}", name),
+            Err(e) => println!("  ❌ {:<8} {}", name, e),
+        }
+    }
+    println!();
+
+    match manager.detect() {
+        Some(backend) => {
+            println!("📋 Copies will go to {}", backend.name());
+            if !backend.can_read_back() {
+                println!("⚠️  It can't read the clipboard back, so it is cleared after the timeout even if");
+                println!("   you have copied something else since.");
+            }
+        }
+        None => {
+            println!("⚠️  No clipboard backend is available. Install wl-clipboard, xclip or xsel, run");
+            println!("   passman inside tmux, or set copy_command under [clipboard] in config.toml.");
+        }
+    }
+    match manager.timeout().as_secs() {
+        0 => println!("   Copies are never cleared (clipboard_timeout = 0)."),
+        seconds => println!("   Copies are cleared after {} seconds.", seconds),
+    }
+}
+
+fn restore_backup(config: &config::Config, number: usize) -> Result<()> {
+    let mut vault_manager = load_vault_manager(config)?;
+    let backups = vault_manager.backups()?;
+
+    let Some(backup) = number.checked_sub(1).and_then(|i| backups.get(i)) else {
+        eprintln!("Error: No backup number {} (see `passman backups list`)", number);
+        return Ok(());
+    };
+
+    if !confirm(&format!("Replace the current vault with {}?", backup.display()))? {
+        println!("Nothing changed.");
+        return Ok(());
+    }
+
+    let preserved = vault_manager.restore_backup(backup)?;
+    println!("✅ Restored {}", backup.display());
+    if let Some(preserved) = preserved {
+        println!("   Previous vault kept at {}", preserved.display());
+    }
+    Ok(())
+}
@@ -1,90 +1,740 @@
-use anyhow::Result;
-use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::time::Duration;
 use std::thread;
+use zeroize::Zeroizing;
 
-#[derive(Clone)]
-pub struct ClipboardManager {
-    timeout: Duration,
+use crate::config::Config;
+
+/// Runs a passman binary as the helper that clears the clipboard, followed
+/// by the number of seconds to wait.
+pub const CLEAR_HELPER_ARG: &str = "--clear-clipboard-after";
+
+/// Every backend name `backends` in `config.toml` accepts.
+pub const BACKEND_NAMES: [&str; 6] = ["command", "wayland", "xclip", "xsel", "tmux", "osc52"];
+
+/// The `[clipboard]` section of `config.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardSettings {
+    /// Backends to try, in order; copies go to the first one that is
+    /// available in the current session
+    pub backends: Vec<String>,
+    /// Program and arguments of the `command` backend, given the text on stdin
+    pub copy_command: Vec<String>,
+    /// Prints the clipboard, so clearing can check it still holds our copy
+    pub paste_command: Vec<String>,
+    /// Empties the clipboard; when left out the copy command is run with no input
+    pub clear_command: Vec<String>,
 }
 
-impl ClipboardManager {
-    pub fn new() -> Result<Self> {
-        Ok(Self {
-            timeout: Duration::from_secs(20),
-        })
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            backends: BACKEND_NAMES.iter().map(|name| name.to_string()).collect(),
+            copy_command: Vec::new(),
+            paste_command: Vec::new(),
+            clear_command: Vec::new(),
+        }
     }
+}
 
-    pub fn copy_to_clipboard(&self, text: &str) -> Result<()> {
-        // Try Wayland first (wl-copy), then fall back to X11 (xclip)
-        if self.try_wayland_copy(text) {
-            return Ok(());
+/// The environment backends look at: the variables that say which display
+/// servers are reachable, and the `PATH` tools are found in. Tools run with
+/// exactly this environment.
+#[derive(Debug, Clone)]
+pub struct Session {
+    vars: HashMap<OsString, OsString>,
+}
+
+impl Session {
+    /// The session this process runs in.
+    pub fn current() -> Self {
+        Self::from_vars(std::env::vars_os())
+    }
+
+    pub fn from_vars<K: Into<OsString>, V: Into<OsString>>(vars: impl IntoIterator<Item = (K, V)>) -> Self {
+        Self { vars: vars.into_iter().map(|(name, value)| (name.into(), value.into())).collect() }
+    }
+
+    fn var(&self, name: &str) -> Option<&OsString> {
+        self.vars.get(&OsString::from(name)).filter(|value| !value.is_empty())
+    }
+
+    fn require_env(&self, var: &str) -> Result<()> {
+        self.var(var).map(|_| ()).ok_or_else(|| anyhow!("{} is not set", var))
+    }
+
+    fn require_program(&self, program: &str) -> Result<()> {
+        self.find_program(program)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("{} is not installed", program))
+    }
+
+    fn find_program(&self, program: &str) -> Option<PathBuf> {
+        if program.contains('/') {
+            return Some(PathBuf::from(program)).filter(|path| path.is_file());
         }
+        std::env::split_paths(self.var("PATH")?)
+            .map(|dir| dir.join(program))
+            .find(|candidate| Path::is_file(candidate))
+    }
 
-        if self.try_x11_copy(text) {
+    /// A command for `program` as found on this session's `PATH`.
+    fn command(&self, program: &str) -> Command {
+        let mut command = Command::new(self.find_program(program).unwrap_or_else(|| PathBuf::from(program)));
+        command.env_clear().envs(&self.vars);
+        command
+    }
+
+    fn run_tool(&self, program: &str, args: &[&str], input: &str) -> Result<()> {
+        if self.run_with_input(program, args, input) {
+            Ok(())
+        } else {
+            Err(anyhow!("{} failed", program))
+        }
+    }
+
+    fn run_command(&self, command: &[String], input: &str) -> Result<()> {
+        let (program, args) = command.split_first().ok_or_else(|| anyhow!("copy_command is not set"))?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.run_tool(program, &args, input)
+    }
+
+    /// Runs a clipboard tool with `input` on its stdin.
+    fn run_with_input(&self, program: &str, args: &[&str], input: &str) -> bool {
+        // wl-copy and xclip fork a process that keeps serving the selection;
+        // it must not hold on to pipes we would otherwise wait on
+        let child = self
+            .command(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            return false;
+        };
+
+        let written = child
+            .stdin
+            .take()
+            .map(|mut stdin| stdin.write_all(input.as_bytes()))
+            .is_some_and(|result| result.is_ok());
+        // Closing stdin (dropped above) lets the tool finish reading
+        let succeeded = child.wait().is_ok_and(|status| status.success());
+        written && succeeded
+    }
+
+    fn read_output(&self, program: &str, args: &[&str]) -> Option<Zeroizing<String>> {
+        let output = self.command(program).args(args).stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+        let text = Zeroizing::new(String::from_utf8_lossy(&output.stdout).into_owned());
+        output.status.success().then_some(text)
+    }
+}
+
+/// One way of reaching a clipboard. Secrets are always handed to tools on
+/// stdin, never as arguments, where any local user could read them from
+/// `ps` or `/proc/<pid>/cmdline`.
+pub trait ClipboardBackend {
+    /// The name used for it in `config.toml`
+    fn name(&self) -> &'static str;
+
+    /// Whether it can work in this session, and if not, why.
+    fn available(&self) -> Result<()>;
+
+    fn copy(&self, text: &str) -> Result<()>;
+
+    /// Whether `read` can report what the clipboard holds.
+    fn can_read_back(&self) -> bool {
+        true
+    }
+
+    /// The clipboard's current text, or `None` if it is empty or can't be read.
+    fn read(&self) -> Option<Zeroizing<String>>;
+
+    fn clear(&self) -> Result<()>;
+}
+
+/// `wl-copy` and `wl-paste` from wl-clipboard.
+struct Wayland {
+    session: Session,
+}
+
+impl ClipboardBackend for Wayland {
+    fn name(&self) -> &'static str {
+        "wayland"
+    }
+
+    fn available(&self) -> Result<()> {
+        self.session.require_env("WAYLAND_DISPLAY")?;
+        self.session.require_program("wl-copy")
+    }
+
+    fn copy(&self, text: &str) -> Result<()> {
+        // --sensitive also offers `x-kde-passwordManagerHint: secret`, which
+        // tells clipboard history managers to skip the copy. wl-clipboard
+        // releases before 2.2 reject the flag, so retry without it
+        if self.session.run_with_input("wl-copy", &["--sensitive"], text) || self.session.run_with_input("wl-copy", &[], text) {
             return Ok(());
         }
+        Err(anyhow!("wl-copy failed"))
+    }
+
+    fn read(&self) -> Option<Zeroizing<String>> {
+        self.session.read_output("wl-paste", &["--no-newline"])
+    }
 
-        Err(anyhow::anyhow!("Failed to copy to clipboard: neither wl-copy nor xclip available"))
+    fn clear(&self) -> Result<()> {
+        self.session.run_tool("wl-copy", &["--clear"], "")
     }
+}
+
+/// X11 through `xclip`. It offers the text under a single type, so copies
+/// can't carry the password manager hint.
+struct Xclip {
+    session: Session,
+}
 
-    fn try_wayland_copy(&self, text: &str) -> bool {
-        Command::new("wl-copy")
-            .arg(text)
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
+impl ClipboardBackend for Xclip {
+    fn name(&self) -> &'static str {
+        "xclip"
     }
 
-    fn try_x11_copy(&self, text: &str) -> bool {
-        Command::new("xclip")
-            .args(&["-selection", "clipboard"])
-            .arg(text)
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
+    fn available(&self) -> Result<()> {
+        self.session.require_env("DISPLAY")?;
+        self.session.require_program("xclip")
     }
 
-    pub fn copy_with_auto_clear(&self, text: &str) -> Result<()> {
-        // Copy to clipboard
-        self.copy_to_clipboard(text)?;
+    fn copy(&self, text: &str) -> Result<()> {
+        self.session.run_tool("xclip", &["-selection", "clipboard"], text)
+    }
+
+    fn read(&self) -> Option<Zeroizing<String>> {
+        self.session.read_output("xclip", &["-selection", "clipboard", "-o"])
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.session.run_tool("xclip", &["-selection", "clipboard"], "")
+    }
+}
 
-        // Schedule auto-clear
-        let timeout = self.timeout;
-        thread::spawn(move || {
-            thread::sleep(timeout);
-            Self::clear_clipboard().ok();
-        });
+/// X11 through `xsel`, for systems that ship it instead of xclip.
+struct Xsel {
+    session: Session,
+}
+
+impl ClipboardBackend for Xsel {
+    fn name(&self) -> &'static str {
+        "xsel"
+    }
+
+    fn available(&self) -> Result<()> {
+        self.session.require_env("DISPLAY")?;
+        self.session.require_program("xsel")
+    }
+
+    fn copy(&self, text: &str) -> Result<()> {
+        self.session.run_tool("xsel", &["--clipboard", "--input"], text)
+    }
+
+    fn read(&self) -> Option<Zeroizing<String>> {
+        self.session.read_output("xsel", &["--clipboard", "--output"])
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.session.run_tool("xsel", &["--clipboard", "--delete"], "")
+    }
+}
+
+/// The paste buffer of the tmux session we run in, pasted with `prefix ]`.
+struct Tmux {
+    session: Session,
+}
+
+impl ClipboardBackend for Tmux {
+    fn name(&self) -> &'static str {
+        "tmux"
+    }
 
+    fn available(&self) -> Result<()> {
+        self.session.require_env("TMUX")?;
+        self.session.require_program("tmux")
+    }
+
+    fn copy(&self, text: &str) -> Result<()> {
+        self.session.run_tool("tmux", &["load-buffer", "-"], text)
+    }
+
+    fn read(&self) -> Option<Zeroizing<String>> {
+        self.session.read_output("tmux", &["show-buffer"])
+    }
+
+    /// Drops the newest buffer, which `clear_if_unchanged` has checked is ours.
+    fn clear(&self) -> Result<()> {
+        self.session.run_tool("tmux", &["delete-buffer"], "")
+    }
+}
+
+/// The OSC 52 escape sequence, which asks the terminal itself to set its
+/// clipboard. It works over SSH, but only in terminals that allow it, and
+/// there is no telling whether the terminal honored it.
+struct Osc52 {
+    session: Session,
+}
+
+impl Osc52 {
+    fn write(&self, payload: &str) -> Result<()> {
+        let mut tty = OpenOptions::new()
+            .write(true)
+            .open("/dev/tty")
+            .context("Could not open the terminal")?;
+        let sequence = Zeroizing::new(osc52_sequence(payload));
+        tty.write_all(sequence.as_bytes())?;
+        tty.flush()?;
         Ok(())
     }
+}
 
-    fn clear_clipboard() -> Result<()> {
-        // Try Wayland first
-        if Command::new("wl-copy")
-            .arg("")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
-        {
+impl ClipboardBackend for Osc52 {
+    fn name(&self) -> &'static str {
+        "osc52"
+    }
+
+    fn available(&self) -> Result<()> {
+        match self.session.var("TERM") {
+            Some(term) if term != "dumb" => {}
+            _ => return Err(anyhow!("TERM is not set to a terminal that could support it")),
+        }
+        OpenOptions::new()
+            .write(true)
+            .open("/dev/tty")
+            .map(|_| ())
+            .map_err(|_| anyhow!("there is no terminal to write to"))
+    }
+
+    fn copy(&self, text: &str) -> Result<()> {
+        self.write(text)
+    }
+
+    // Terminals either don't answer clipboard queries or ask the user first
+    fn can_read_back(&self) -> bool {
+        false
+    }
+
+    fn read(&self) -> Option<Zeroizing<String>> {
+        None
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.write("")
+    }
+}
+
+/// Any tool given as `copy_command` in `config.toml`, such as `pbcopy` or
+/// `clip.exe`.
+struct CustomCommand {
+    session: Session,
+    copy: Vec<String>,
+    paste: Vec<String>,
+    clear: Vec<String>,
+}
+
+impl ClipboardBackend for CustomCommand {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn available(&self) -> Result<()> {
+        let program = self.copy.first().ok_or_else(|| anyhow!("copy_command is not set"))?;
+        self.session.require_program(program)
+    }
+
+    fn copy(&self, text: &str) -> Result<()> {
+        self.session.run_command(&self.copy, text)
+    }
+
+    fn can_read_back(&self) -> bool {
+        !self.paste.is_empty()
+    }
+
+    fn read(&self) -> Option<Zeroizing<String>> {
+        let (program, args) = self.paste.split_first()?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.session.read_output(program, &args)
+    }
+
+    fn clear(&self) -> Result<()> {
+        if self.clear.is_empty() {
+            self.session.run_command(&self.copy, "")
+        } else {
+            self.session.run_command(&self.clear, "")
+        }
+    }
+}
+
+/// Looks up a backend by the name used in `config.toml`.
+pub fn backend_named(name: &str, settings: &ClipboardSettings, session: &Session) -> Result<Box<dyn ClipboardBackend>> {
+    let session = session.clone();
+    let backend: Box<dyn ClipboardBackend> = match name.to_ascii_lowercase().as_str() {
+        "command" => Box::new(CustomCommand {
+            session,
+            copy: settings.copy_command.clone(),
+            paste: settings.paste_command.clone(),
+            clear: settings.clear_command.clone(),
+        }),
+        "wayland" => Box::new(Wayland { session }),
+        "xclip" => Box::new(Xclip { session }),
+        "xsel" => Box::new(Xsel { session }),
+        "tmux" => Box::new(Tmux { session }),
+        "osc52" => Box::new(Osc52 { session }),
+        other => {
+            return Err(anyhow!(
+                "Unknown clipboard backend '{}' (expected {})",
+                other,
+                BACKEND_NAMES.join(", ")
+            ))
+        }
+    };
+    Ok(backend)
+}
+
+#[derive(Clone)]
+pub struct ClipboardManager {
+    timeout: Duration,
+    settings: ClipboardSettings,
+    session: Session,
+}
+
+impl ClipboardManager {
+    /// Clears copies after `clipboard_timeout` seconds, or never if it is 0.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            timeout: Duration::from_secs(config.clipboard_timeout),
+            settings: config.clipboard.clone(),
+            session: Session::current(),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The configured backends in the order they are tried; unknown names
+    /// come back as errors.
+    pub fn backends(&self) -> Vec<(String, Result<Box<dyn ClipboardBackend>>)> {
+        self.settings
+            .backends
+            .iter()
+            .map(|name| (name.clone(), backend_named(name, &self.settings, &self.session)))
+            .collect()
+    }
+
+    /// The backend copies will go to, if any works in this session.
+    pub fn detect(&self) -> Option<Box<dyn ClipboardBackend>> {
+        self.available_backends().next()
+    }
+
+    fn available_backends(&self) -> impl Iterator<Item = Box<dyn ClipboardBackend>> + '_ {
+        self.backends()
+            .into_iter()
+            .filter_map(|(_, backend)| backend.ok())
+            .filter(|backend| backend.available().is_ok())
+    }
+}
+
+// Only the TUI copies; the CLI uses the rest of this module for `doctor`
+#[allow(dead_code)]
+impl ClipboardManager {
+    pub fn copy_to_clipboard(&self, text: &str) -> Result<()> {
+        self.copy(text).map(|_| ())
+    }
+
+    /// Copies with the first available backend that succeeds, and returns it.
+    fn copy(&self, text: &str) -> Result<Box<dyn ClipboardBackend>> {
+        let mut last_error = None;
+        for backend in self.available_backends() {
+            match backend.copy(text) {
+                Ok(()) => return Ok(backend),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(e.context("Failed to copy to clipboard")),
+            None => Err(anyhow!(
+                "Failed to copy to clipboard: no clipboard backend is available (see `passman doctor clipboard`)"
+            )),
+        }
+    }
+
+    /// Copies `text` and schedules the clipboard to be cleared. The clearing
+    /// is left to a detached helper process so it still happens when we
+    /// exit straight after copying.
+    pub fn copy_with_auto_clear(&self, text: &str) -> Result<()> {
+        let backend = self.copy(text)?;
+        if self.timeout.is_zero() {
             return Ok(());
         }
+        self.spawn_clear_helper(&content_hash(text), backend.name())
+    }
 
-        // Fall back to X11
-        if Command::new("xclip")
-            .args(&["-selection", "clipboard"])
-            .arg("")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
+    /// The helper is told which backend was used and the hash of what we
+    /// copied over its stdin, so it can leave the clipboard alone if the
+    /// user has copied something else.
+    fn spawn_clear_helper(&self, hash: &str, backend: &str) -> Result<()> {
+        let executable = std::env::current_exe().context("Could not find the passman executable")?;
+        let mut command = Command::new(executable);
+        command
+            .arg(CLEAR_HELPER_ARG)
+            .arg(self.timeout.as_secs().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // Its own process group keeps Ctrl+C in the terminal from killing it
+        #[cfg(unix)]
         {
-            return Ok(());
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+
+        let mut child = command.spawn().context("Could not start the clipboard clearing helper")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            writeln!(stdin, "{}\n{}", hash, backend).context("Could not start the clipboard clearing helper")?;
+        }
+        // Reap the helper if it finishes while we are still running
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+/// Clears the clipboard if it still holds the text hashed to `expected`.
+/// Backends that can't read the clipboard back are cleared regardless.
+/// Returns whether it was cleared.
+fn clear_if_unchanged(backend: &dyn ClipboardBackend, expected: &str) -> Result<bool> {
+    if backend.can_read_back() {
+        let Some(current) = backend.read() else {
+            return Ok(false);
+        };
+        if content_hash(&current) != expected {
+            return Ok(false);
         }
+    }
+    backend.clear()?;
+    Ok(true)
+}
+
+/// `ESC ] 52 ; c ; <base64> BEL`, setting the clipboard selection.
+fn osc52_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", BASE64.encode(text.as_bytes()))
+}
+
+fn content_hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Body of the helper process started by `copy_with_auto_clear`: reads the
+/// hash of the copied text and the backend it went to from stdin, waits
+/// out the timeout, then clears the clipboard unless something else has
+/// been copied since.
+#[allow(dead_code)] // Started by the TUI only
+pub fn run_clear_helper(seconds: Option<&str>) -> Result<()> {
+    let seconds: u64 = seconds
+        .and_then(|seconds| seconds.parse().ok())
+        .ok_or_else(|| anyhow!("{} needs a number of seconds", CLEAR_HELPER_ARG))?;
+    let mut lines = std::io::stdin().lock().lines();
+    let hash = lines.next().transpose()?.unwrap_or_default();
+    let name = lines.next().transpose()?.unwrap_or_default();
+    // The custom command backend needs its settings
+    let settings = Config::load().map(|config| config.clipboard).unwrap_or_default();
+    let backend = backend_named(name.trim(), &settings, &Session::current())?;
+
+    thread::sleep(Duration::from_secs(seconds));
+    clear_if_unchanged(backend.as_ref(), hash.trim())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const SECRET: &str = "correct horse battery staple";
 
-        Err(anyhow::anyhow!("Failed to clear clipboard"))
+    /// Creates a directory of fake clipboard tools that log their command
+    /// line to `args.log` and their stdin to `stdin.log`. Reading (`-o`,
+    /// `--output`, `show-buffer` or `wl-paste`) prints `clipboard.txt`
+    /// instead.
+    fn fake_tools(test: &str, tools: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("passman-clipboard-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for tool in tools {
+            let path = dir.join(tool);
+            let script = format!(
+                "#!/bin/sh\necho \"$0 $*\" >> '{dir}/args.log'\n\
+                 case \"$0 $*\" in *wl-paste*|*' -o'|*--output|*show-buffer) exec /bin/cat '{dir}/clipboard.txt' ;; esac\n\
+                 /bin/cat >> '{dir}/stdin.log'\n",
+                dir = dir.display()
+            );
+            fs::write(&path, script).unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            }
+        }
+        dir
+    }
+
+    /// A session with only the fake tools on PATH, which looks like it has
+    /// Wayland, X11 and tmux.
+    fn session(dir: &Path) -> Session {
+        Session::from_vars([
+            ("PATH", dir.as_os_str()),
+            ("WAYLAND_DISPLAY", "wayland-test".as_ref()),
+            ("DISPLAY", ":99".as_ref()),
+            ("TMUX", "/tmp/tmux-test/default,1,0".as_ref()),
+        ])
+    }
+
+    /// A manager trying `backends` in order. OSC 52 is left out of the
+    /// defaults here, as it would write to the terminal running the tests.
+    fn manager(dir: &Path, backends: &[&str], copy_command: &[&str]) -> ClipboardManager {
+        let mut config = Config::default();
+        config.clipboard.backends = backends.iter().map(|name| name.to_string()).collect();
+        config.clipboard.copy_command = copy_command.iter().map(|arg| arg.to_string()).collect();
+        ClipboardManager { session: session(dir), ..ClipboardManager::new(&config) }
+    }
+
+    fn default_manager(dir: &Path) -> ClipboardManager {
+        manager(dir, &["command", "wayland", "xclip", "xsel", "tmux"], &[])
     }
 
-    pub fn set_timeout(&mut self, timeout: Duration) {
-        self.timeout = timeout;
+    fn read_log(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap_or_default()
+    }
+
+    #[test]
+    fn wayland_copy_sends_secret_over_stdin() {
+        let dir = fake_tools("wayland", &["wl-copy", "xclip"]);
+        default_manager(&dir).copy_to_clipboard(SECRET).unwrap();
+
+        assert_eq!(read_log(&dir, "stdin.log"), SECRET);
+        let args = read_log(&dir, "args.log");
+        assert!(args.contains("wl-copy --sensitive"), "wl-copy was not run: {:?}", args);
+        assert!(!args.contains(SECRET), "secret leaked into a command line: {:?}", args);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn x11_copy_sends_secret_over_stdin() {
+        let dir = fake_tools("x11", &["xclip"]);
+        default_manager(&dir).copy_to_clipboard(SECRET).unwrap();
+
+        assert_eq!(read_log(&dir, "stdin.log"), SECRET);
+        let args = read_log(&dir, "args.log");
+        assert!(args.contains("xclip -selection clipboard"), "xclip was not run: {:?}", args);
+        assert!(!args.contains(SECRET), "secret leaked into a command line: {:?}", args);
+        fs::remove_dir_all(dir).ok();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn backends_are_tried_in_configured_order() {
+        let dir = fake_tools("order", &["xclip", "xsel", "tmux"]);
+        let detected = manager(&dir, &["tmux", "xsel", "xclip"], &[]).detect().map(|b| b.name());
+        assert_eq!(detected, Some("tmux"));
+
+        manager(&dir, &["xsel", "xclip"], &[]).copy_to_clipboard(SECRET).unwrap();
+        assert_eq!(read_log(&dir, "args.log"), format!("{}/xsel --clipboard --input\n", dir.display()));
+        assert_eq!(read_log(&dir, "stdin.log"), SECRET);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn custom_command_gets_secret_over_stdin() {
+        let dir = fake_tools("command", &["mycopy", "xclip"]);
+        manager(&dir, &["command", "xclip"], &["mycopy", "--from-passman"]).copy_to_clipboard(SECRET).unwrap();
+
+        assert_eq!(read_log(&dir, "stdin.log"), SECRET);
+        assert!(read_log(&dir, "args.log").ends_with("mycopy --from-passman\n"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn tmux_buffer_is_dropped_only_while_it_holds_the_secret() {
+        let dir = fake_tools("tmux", &["tmux"]);
+        manager(&dir, &["tmux"], &[]).copy_to_clipboard(SECRET).unwrap();
+        assert_eq!(read_log(&dir, "stdin.log"), SECRET);
+
+        fs::write(dir.join("clipboard.txt"), SECRET).unwrap();
+        assert!(clear_if_unchanged(&Tmux { session: session(&dir) }, &content_hash(SECRET)).unwrap());
+        assert!(read_log(&dir, "args.log").ends_with("tmux delete-buffer\n"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn osc52_sequence_is_base64_encoded() {
+        assert_eq!(osc52_sequence("hunter2"), "\x1b]52;c;aHVudGVyMg==\x07");
+        assert_eq!(osc52_sequence(""), "\x1b]52;c;\x07");
+    }
+
+    #[test]
+    fn clearing_passes_nothing_on_the_command_line() {
+        let dir = fake_tools("clear", &["xclip"]);
+        Xclip { session: session(&dir) }.clear().unwrap();
+
+        assert_eq!(read_log(&dir, "stdin.log"), "");
+        assert!(read_log(&dir, "args.log").contains("xclip -selection clipboard"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn clears_clipboard_still_holding_the_secret() {
+        let dir = fake_tools("unchanged", &["xclip"]);
+        fs::write(dir.join("clipboard.txt"), SECRET).unwrap();
+        let cleared = clear_if_unchanged(&Xclip { session: session(&dir) }, &content_hash(SECRET)).unwrap();
+
+        assert!(cleared);
+        assert!(read_log(&dir, "args.log").ends_with("xclip -selection clipboard\n"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn keeps_clipboard_the_user_has_replaced() {
+        let dir = fake_tools("replaced", &["xclip"]);
+        fs::write(dir.join("clipboard.txt"), "something the user copied").unwrap();
+        let cleared = clear_if_unchanged(&Xclip { session: session(&dir) }, &content_hash(SECRET)).unwrap();
+
+        assert!(!cleared);
+        assert_eq!(read_log(&dir, "stdin.log"), "", "the clipboard was overwritten");
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn copy_fails_without_clipboard_tools() {
+        let dir = fake_tools("none", &[]);
+        assert!(default_manager(&dir).copy_to_clipboard(SECRET).is_err());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn backends_need_their_session_variables() {
+        let dir = fake_tools("variables", &["wl-copy", "xclip", "tmux"]);
+        let bare = Session::from_vars([("PATH", dir.as_os_str())]);
+        let settings = ClipboardSettings::default();
+        for name in ["wayland", "xclip", "tmux"] {
+            let error = backend_named(name, &settings, &bare).unwrap().available().unwrap_err();
+            assert!(error.to_string().ends_with("is not set"), "{}: {}", name, error);
+            assert!(backend_named(name, &settings, &session(&dir)).unwrap().available().is_ok());
+        }
+        fs::remove_dir_all(dir).ok();
+    }
+}
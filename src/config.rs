@@ -1,15 +1,38 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use dirs;
+use std::path::{Path, PathBuf};
 
+use crate::clipboard::ClipboardSettings;
+use crate::generator::{PassphrasePolicy, PasswordPolicy};
+use crate::search::SortOrder;
+
+/// Environment variable that overrides the vault location from `config.toml`
+pub const VAULT_ENV_VAR: &str = "PASSMAN_VAULT";
+
+// Missing keys fall back to `Config::default()`, so config files written by
+// older versions keep loading as new settings are added.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub clipboard_timeout: u64,
+    /// Minutes without input before the TUI locks the vault; 0 never locks
     pub auto_lock_minutes: u64,
     pub theme: String,
     pub window_center: bool,
+    /// Order of search results: frecency, recent, alphabetical or match
+    pub sort: SortOrder,
     pub vault_path: PathBuf,
+    /// Number of previous vault generations kept as `vault.json.1..N`
+    pub backup_count: usize,
+    /// Lowest strength score (0-4) a new password may have; 0 accepts any
+    pub min_password_score: u8,
+    pub generator: PasswordPolicy,
+    pub passphrase: PassphrasePolicy,
+    pub clipboard: ClipboardSettings,
+    /// Set by `with_vault_override` when `--vault` or `PASSMAN_VAULT` chose
+    /// the vault rather than the config file
+    #[serde(skip)]
+    pub vault_overridden: bool,
 }
 
 impl Default for Config {
@@ -19,7 +42,14 @@ impl Default for Config {
             auto_lock_minutes: 5,
             theme: "dark".to_string(),
             window_center: true,
+            sort: SortOrder::default(),
             vault_path: Self::default_vault_path(),
+            backup_count: 5,
+            min_password_score: 0,
+            generator: PasswordPolicy::default(),
+            passphrase: PassphrasePolicy::default(),
+            clipboard: ClipboardSettings::default(),
+            vault_overridden: false,
         }
     }
 }
@@ -39,6 +69,22 @@ impl Config {
         }
     }
 
+    /// Resolves the vault location, in order of precedence: the `--vault`
+    /// command line flag, `PASSMAN_VAULT`, then `vault_path` from the config
+    /// file (which itself defaults to the data directory).
+    pub fn with_vault_override(mut self, cli_vault: Option<&Path>) -> Self {
+        let env_vault = std::env::var_os(VAULT_ENV_VAR)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from);
+
+        if let Some(path) = cli_vault.map(Path::to_path_buf).or(env_vault) {
+            self.vault_path = path;
+            self.vault_overridden = true;
+        }
+        self.vault_path = expand_home(&self.vault_path);
+        self
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_path = Self::config_path();
         let config_dir = config_path.parent().unwrap();
@@ -67,3 +113,11 @@ impl Config {
             .join("vault.db")
     }
 }
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
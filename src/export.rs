@@ -0,0 +1,354 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use crate::crypto::VaultKey;
+use crate::vault::{PasswordEntry, VaultDocument};
+
+/// Formats `passman export` can write.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Dotenv,
+    Sheet,
+    Kdbx,
+    Pass,
+}
+
+impl ExportFormat {
+    /// Parses the value of `--format`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "dotenv" | "env" => Ok(Self::Dotenv),
+            "sheet" | "paper" => Ok(Self::Sheet),
+            "kdbx" | "keepass" => Ok(Self::Kdbx),
+            "pass" | "password-store" => Ok(Self::Pass),
+            other => Err(anyhow!(
+                "Unknown export format '{}' (expected json, csv, dotenv, sheet, kdbx or pass)",
+                other
+            )),
+        }
+    }
+
+    /// Guesses the format from the file name, e.g. `backup.kdbx`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name == ".env" || name.ends_with(".env") {
+            return Some(Self::Dotenv);
+        }
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "txt" | "html" | "htm" => Some(Self::Sheet),
+            "kdbx" => Some(Self::Kdbx),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Json => "encrypted JSON",
+            Self::Csv => "plain text CSV",
+            Self::Dotenv => "dotenv",
+            Self::Sheet => "printable sheet",
+            Self::Kdbx => "KeePass KDBX 4",
+            Self::Pass => "password-store",
+        }
+    }
+
+    /// Whether the file holds passwords readable without any key.
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self, Self::Csv | Self::Dotenv | Self::Sheet)
+    }
+}
+
+/// The whole vault document sealed under its own password, in the same
+/// container as the vault file. It can be imported again or opened directly
+/// with `--vault`.
+pub fn to_encrypted_json(entries: &[PasswordEntry], password: &str) -> Result<String> {
+    let document = VaultDocument {
+        entries: entries.to_vec(),
+        ..VaultDocument::default()
+    };
+    let plaintext = Zeroizing::new(serde_json::to_vec(&document)?);
+    let key = VaultKey::generate(password)?;
+    Ok(serde_json::to_string_pretty(&key.seal(&plaintext)?)?)
+}
+
+/// One row per entry. Custom fields are left out; use JSON or KDBX to keep
+/// everything.
+pub fn to_csv(entries: &[PasswordEntry]) -> Result<Zeroizing<String>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["name", "url", "username", "password", "notes", "tags", "folder", "otp"])?;
+    for entry in entries {
+        let otp = entry.otp.as_ref().map(|otp| otp.to_uri(&entry.name)).unwrap_or_default();
+        writer.write_record([
+            entry.name.as_str(),
+            entry.url.as_deref().unwrap_or_default(),
+            entry.username.as_deref().unwrap_or_default(),
+            entry.password.as_str(),
+            entry.notes.as_deref().unwrap_or_default(),
+            &entry.tags.join(","),
+            entry.folder.as_deref().unwrap_or_default(),
+            &otp,
+        ])?;
+    }
+    let data = Zeroizing::new(writer.into_inner().map_err(|e| anyhow!("Could not write CSV: {}", e))?);
+    Ok(Zeroizing::new(String::from_utf8(data.to_vec())?))
+}
+
+/// `NAME_USERNAME`, `NAME_PASSWORD` and so on for each entry, ready to be
+/// sourced by a shell or loaded by a dotenv library. Names that would clash,
+/// such as those of `GitHub` and `github`, are numbered: `GITHUB_2_PASSWORD`.
+pub fn to_dotenv(entries: &[PasswordEntry]) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::new());
+    let mut prefixes = HashSet::new();
+    let mut names = HashSet::new();
+    for entry in entries {
+        let prefix = numbered(&env_name(&entry.name), |prefix| !prefixes.contains(prefix));
+        prefixes.insert(prefix.clone());
+        out.push_str(&format!("# {}\n", entry.name.replace('\n', " ")));
+        let mut line = |suffix: &str, value: &str| {
+            // A custom field can still clash, e.g. one named `password`
+            let name = numbered(&format!("{}_{}", prefix, env_name(suffix)), |name| !names.contains(name));
+            out.push_str(&format!("{}=\"{}\"\n", name, env_escape(value)));
+            names.insert(name);
+        };
+        if let Some(username) = &entry.username {
+            line("USERNAME", username);
+        }
+        line("PASSWORD", &entry.password);
+        if let Some(url) = &entry.url {
+            line("URL", url);
+        }
+        for field in &entry.custom_fields {
+            line(&field.name, &field.value);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Upper snake case, e.g. `GitHub (work)` becomes `GITHUB_WORK`.
+fn env_name(name: &str) -> String {
+    let mut result = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            result.push(c.to_ascii_uppercase());
+        } else if !result.is_empty() && !result.ends_with('_') {
+            result.push('_');
+        }
+    }
+    let result = result.trim_end_matches('_').to_string();
+    match result.chars().next() {
+        None => "ENTRY".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("_{}", result),
+        Some(_) => result,
+    }
+}
+
+/// `base`, or `base_2`, `base_3` and so on if it is taken.
+fn numbered(base: &str, is_free: impl Fn(&str) -> bool) -> String {
+    if is_free(base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{}_{}", base, n))
+        .find(|name| is_free(name))
+        .expect("some numbered name is free")
+}
+
+fn env_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('`', "\\`")
+        .replace('\n', "\\n")
+}
+
+/// Entries listed under each of their tags, for printing and storing
+/// somewhere safe. Written as HTML when `html` is set, plain text otherwise.
+pub fn to_sheet(entries: &[PasswordEntry], html: bool) -> Zeroizing<String> {
+    let mut groups: BTreeMap<&str, Vec<&PasswordEntry>> = BTreeMap::new();
+    let mut untagged = Vec::new();
+    for entry in entries {
+        if entry.tags.is_empty() {
+            untagged.push(entry);
+        }
+        for tag in &entry.tags {
+            groups.entry(tag.as_str()).or_default().push(entry);
+        }
+    }
+    let mut groups: Vec<(&str, Vec<&PasswordEntry>)> = groups.into_iter().collect();
+    if !untagged.is_empty() {
+        groups.push(("Untagged", untagged));
+    }
+    for (_, entries) in &mut groups {
+        entries.sort_by_key(|entry| entry.name.to_lowercase());
+    }
+
+    let printed = Local::now().format("%Y-%m-%d %H:%M");
+    if html {
+        sheet_html(&groups, &printed.to_string(), entries.len())
+    } else {
+        sheet_text(&groups, &printed.to_string(), entries.len())
+    }
+}
+
+/// Label and value of each detail worth writing down for an entry.
+fn sheet_fields(entry: &PasswordEntry) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    if let Some(folder) = &entry.folder {
+        fields.push(("Folder".to_string(), folder.clone()));
+    }
+    if let Some(username) = &entry.username {
+        fields.push(("Username".to_string(), username.clone()));
+    }
+    fields.push(("Password".to_string(), entry.password.clone()));
+    if let Some(url) = &entry.url {
+        fields.push(("URL".to_string(), url.clone()));
+    }
+    if let Some(otp) = &entry.otp {
+        fields.push(("One-time code key".to_string(), otp.secret.clone()));
+    }
+    for field in &entry.custom_fields {
+        fields.push((field.name.clone(), field.value.clone()));
+    }
+    if let Some(notes) = &entry.notes {
+        fields.push(("Notes".to_string(), notes.clone()));
+    }
+    fields
+}
+
+fn sheet_text(groups: &[(&str, Vec<&PasswordEntry>)], printed: &str, count: usize) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::new());
+    out.push_str("PASSMAN EMERGENCY SHEET\n");
+    out.push_str(&format!("Printed {}, {} entries\n", printed, count));
+    out.push_str("Keep this somewhere safe and destroy older copies.\n");
+    for (tag, entries) in groups {
+        out.push_str(&format!("\n== {} ==\n", tag));
+        for entry in entries {
+            out.push_str(&format!("\n{}\n", entry.name));
+            for (label, value) in sheet_fields(entry) {
+                let value = value.replace('\n', "\n      ");
+                out.push_str(&format!("  {}: {}\n", label, value));
+            }
+        }
+    }
+    out
+}
+
+fn sheet_html(groups: &[(&str, Vec<&PasswordEntry>)], printed: &str, count: usize) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::new());
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Passman emergency sheet</title>\n");
+    out.push_str(
+        "<style>\nbody { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; width: 100%; margin-bottom: 1em; page-break-inside: avoid; }\n\
+         th, td { border: 1px solid #999; padding: 4px 8px; text-align: left; vertical-align: top; }\n\
+         th { width: 12em; background: #eee; }\n\
+         td { font-family: monospace; white-space: pre-wrap; word-break: break-all; }\n\
+         h2 { page-break-after: avoid; }\n</style>\n</head>\n<body>\n",
+    );
+    out.push_str("<h1>Passman emergency sheet</h1>\n");
+    out.push_str(&format!(
+        "<p>Printed {}, {} entries. Keep this somewhere safe and destroy older copies.</p>\n",
+        html_escape(printed),
+        count
+    ));
+    for (tag, entries) in groups {
+        out.push_str(&format!("<h2>{}</h2>\n", html_escape(tag)));
+        for entry in entries {
+            out.push_str(&format!("<table>\n<caption><strong>{}</strong></caption>\n", html_escape(&entry.name)));
+            for (label, value) in sheet_fields(entry) {
+                out.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", html_escape(&label), html_escape(&value)));
+            }
+            out.push_str("</table>\n");
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::CustomField;
+
+    fn entry(name: &str, password: &str) -> PasswordEntry {
+        PasswordEntry::new(name, password)
+    }
+
+    #[test]
+    fn csv_quotes_commas_quotes_and_newlines() {
+        let mut tricky = entry("Bank, \"main\"", "p,a\"ss\nword");
+        tricky.notes = Some("line one\nline two".to_string());
+        tricky.tags = vec!["money".to_string(), "home".to_string()];
+        let csv = to_csv(&[tricky]).unwrap();
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(&row[0], "Bank, \"main\"");
+        assert_eq!(&row[3], "p,a\"ss\nword");
+        assert_eq!(&row[4], "line one\nline two");
+        assert_eq!(&row[5], "money,home");
+    }
+
+    #[test]
+    fn env_names_are_upper_snake_case() {
+        assert_eq!(env_name("GitHub (work)"), "GITHUB_WORK");
+        assert_eq!(env_name("my-db.example.com"), "MY_DB_EXAMPLE_COM");
+        assert_eq!(env_name("1password"), "_1PASSWORD");
+        assert_eq!(env_name("Ünïcode!"), "N_CODE");
+        assert_eq!(env_name("???"), "ENTRY");
+    }
+
+    #[test]
+    fn dotenv_values_cannot_break_out_of_their_quotes() {
+        let dotenv = to_dotenv(&[entry("db", "a\"b$HOME`id`\\n\nnext")]);
+        assert!(dotenv.contains("DB_PASSWORD=\"a\\\"b\\$HOME\\`id\\`\\\\n\\nnext\"\n"), "{}", *dotenv);
+        assert_eq!(dotenv.lines().filter(|line| line.starts_with("DB_")).count(), 1);
+    }
+
+    #[test]
+    fn dotenv_numbers_names_that_would_clash() {
+        let mut second = entry("github", "two");
+        second.custom_fields = vec![CustomField { name: "password".to_string(), value: "three".to_string(), protected: true }];
+        let dotenv = to_dotenv(&[entry("GitHub", "one"), second]);
+
+        let variables: Vec<&str> = dotenv.lines().filter(|line| !line.starts_with('#') && !line.is_empty()).collect();
+        assert_eq!(
+            variables,
+            ["GITHUB_PASSWORD=\"one\"", "GITHUB_2_PASSWORD=\"two\"", "GITHUB_2_PASSWORD_2=\"three\""]
+        );
+    }
+
+    #[test]
+    fn sheets_escape_html_and_indent_multi_line_text() {
+        let mut tricky = entry("<script>", "a<b&c>\"d'");
+        tricky.notes = Some("first\nsecond".to_string());
+
+        let html = to_sheet(std::slice::from_ref(&tricky), true);
+        assert!(html.contains("<strong>&lt;script&gt;</strong>"));
+        assert!(html.contains("<td>a&lt;b&amp;c&gt;&quot;d&#39;</td>"));
+        assert!(!html.contains("<script>"));
+
+        let text = to_sheet(&[tricky], false);
+        assert!(text.contains("  Password: a<b&c>\"d'\n"));
+        assert!(text.contains("  Notes: first\n      second\n"));
+        assert!(text.contains("== Untagged =="));
+    }
+}
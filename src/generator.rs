@@ -0,0 +1,269 @@
+use anyhow::{anyhow, Context, Result};
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>/?~|`'\"\\";
+
+/// Characters that are easily confused with one another in common fonts
+const AMBIGUOUS: &str = "Il1|O0o`'\"";
+
+/// The BIP-39 English wordlist: 2048 common words (11 bits each), each
+/// identifiable by its first four letters
+const BUNDLED_WORDLIST: &str = include_str!("wordlists/bip39_english.txt");
+
+/// A generated password or passphrase together with its strength
+pub struct Generated {
+    pub secret: String,
+    /// Bits of entropy, assuming the attacker knows the policy used
+    pub entropy_bits: f64,
+}
+
+/// Rules for random-character passwords, configurable under `[generator]`
+/// in `config.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub exclude_ambiguous: bool,
+    /// Guarantee at least one character from every enabled class
+    pub require_each_class: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+            require_each_class: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The character set of every enabled class, with ambiguous characters
+    /// removed if requested and `excluded` characters always removed.
+    fn classes(&self, excluded: &str) -> Vec<Vec<char>> {
+        [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| {
+            chars
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .filter(|c| !excluded.contains(*c))
+                .collect::<Vec<char>>()
+        })
+        .filter(|class| !class.is_empty())
+        .collect()
+    }
+}
+
+/// Rules for diceware-style passphrases, configurable under `[passphrase]`
+/// in `config.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassphrasePolicy {
+    /// Generate passphrases instead of passwords when no mode is asked for
+    pub default: bool,
+    /// Seven words from the bundled 2048-word list give 77 bits
+    pub words: usize,
+    pub separator: String,
+    /// Capitalize the first letter of every word
+    pub capitalize: bool,
+    /// Append a random digit to one randomly chosen word
+    pub insert_digit: bool,
+    /// Wordlist to use instead of the bundled one, one word per line. Lines
+    /// in the diceware `<dice>\t<word>` format (such as the EFF large
+    /// wordlist) are accepted as well.
+    pub wordlist_path: Option<PathBuf>,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        Self {
+            default: false,
+            words: 7,
+            separator: "-".to_string(),
+            capitalize: false,
+            insert_digit: false,
+            wordlist_path: None,
+        }
+    }
+}
+
+impl PassphrasePolicy {
+    /// Loads the configured wordlist, or the bundled one if none is set.
+    fn wordlist(&self) -> Result<Vec<String>> {
+        let content = match &self.wordlist_path {
+            Some(path) => std::fs::read_to_string(crate::config::expand_home(path))
+                .with_context(|| format!("Could not read wordlist {}", path.display()))?,
+            None => BUNDLED_WORDLIST.to_string(),
+        };
+
+        // Duplicates would make some words more likely and overstate entropy
+        let mut seen = HashSet::new();
+        let words: Vec<String> = content
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .filter(|word| seen.insert(word.to_string()))
+            .map(str::to_string)
+            .collect();
+
+        if words.len() < 2 {
+            return Err(anyhow!("The wordlist must contain at least two distinct words"));
+        }
+        Ok(words)
+    }
+}
+
+/// Generates a password from the operating system's CSPRNG.
+#[allow(dead_code)] // The TUI always leaves out its field separator
+pub fn generate_password(policy: &PasswordPolicy) -> Result<Generated> {
+    generate_password_without(policy, "")
+}
+
+/// Generates a password that contains none of the `excluded` characters,
+/// for inputs that give some characters a meaning of their own.
+pub fn generate_password_without(policy: &PasswordPolicy, excluded: &str) -> Result<Generated> {
+    let classes = policy.classes(excluded);
+    if classes.is_empty() {
+        return Err(anyhow!("At least one character class must be enabled"));
+    }
+    if policy.length == 0 {
+        return Err(anyhow!("Password length must be at least 1"));
+    }
+    if policy.require_each_class && policy.length < classes.len() {
+        return Err(anyhow!(
+            "Password length {} is too short to include all {} character classes",
+            policy.length,
+            classes.len()
+        ));
+    }
+
+    let alphabet: Vec<char> = classes.concat();
+    let mut rng = OsRng;
+    let mut password = Vec::with_capacity(policy.length);
+
+    if policy.require_each_class {
+        for class in &classes {
+            password.push(class[rng.gen_range(0..class.len())]);
+        }
+    }
+    while password.len() < policy.length {
+        password.push(alphabet[rng.gen_range(0..alphabet.len())]);
+    }
+
+    // The required characters were placed first; move them to random spots
+    password.shuffle(&mut rng);
+    Ok(Generated {
+        secret: password.into_iter().collect(),
+        entropy_bits: policy.length as f64 * (alphabet.len() as f64).log2(),
+    })
+}
+
+/// Generates a passphrase of random words from the operating system's CSPRNG.
+pub fn generate_passphrase(policy: &PassphrasePolicy) -> Result<Generated> {
+    if policy.words == 0 {
+        return Err(anyhow!("A passphrase needs at least one word"));
+    }
+
+    let wordlist = policy.wordlist()?;
+    let mut rng = OsRng;
+    let mut words: Vec<String> = (0..policy.words)
+        .map(|_| wordlist[rng.gen_range(0..wordlist.len())].clone())
+        .collect();
+
+    if policy.capitalize {
+        for word in &mut words {
+            let mut chars = word.chars();
+            if let Some(first) = chars.next() {
+                *word = first.to_uppercase().chain(chars).collect();
+            }
+        }
+    }
+
+    let mut entropy_bits = policy.words as f64 * (wordlist.len() as f64).log2();
+    if policy.insert_digit {
+        let index = rng.gen_range(0..words.len());
+        words[index].push(char::from(b'0' + rng.gen_range(0..10u8)));
+        entropy_bits += (10.0 * words.len() as f64).log2();
+    }
+
+    Ok(Generated {
+        secret: words.join(&policy.separator),
+        entropy_bits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_passphrases_have_77_bits() {
+        let policy = PassphrasePolicy::default();
+        let wordlist = policy.wordlist().unwrap();
+        assert_eq!(wordlist.len(), 2048);
+        assert_eq!((wordlist.len() as f64).log2(), 11.0);
+
+        let generated = generate_passphrase(&policy).unwrap();
+        assert_eq!(generated.secret.split('-').count(), 7);
+        assert_eq!(generated.entropy_bits, 77.0);
+    }
+
+    #[test]
+    fn reads_diceware_wordlists() {
+        let path = std::env::temp_dir().join(format!("passman-wordlist-{}", std::process::id()));
+        std::fs::write(&path, "11111\tabacus\n11112\tabdomen\n11113\tabdominal\n11114\tabide\n11114\tabide\n").unwrap();
+        let policy = PassphrasePolicy {
+            words: 3,
+            wordlist_path: Some(path.clone()),
+            ..PassphrasePolicy::default()
+        };
+
+        assert_eq!(policy.wordlist().unwrap(), ["abacus", "abdomen", "abdominal", "abide"]);
+        let generated = generate_passphrase(&policy).unwrap();
+        assert_eq!(generated.entropy_bits, 6.0);
+        assert!(generated.secret.split('-').all(|word| word.starts_with("ab")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn digit_insertion_adds_its_entropy() {
+        let policy = PassphrasePolicy {
+            words: 4,
+            insert_digit: true,
+            ..PassphrasePolicy::default()
+        };
+        let generated = generate_passphrase(&policy).unwrap();
+        assert_eq!(generated.secret.chars().filter(char::is_ascii_digit).count(), 1);
+        assert_eq!(generated.entropy_bits, 44.0 + 40f64.log2());
+    }
+
+    #[test]
+    fn passwords_include_every_required_class() {
+        let generated = generate_password(&PasswordPolicy::default()).unwrap();
+        assert_eq!(generated.secret.chars().count(), 20);
+        for class in [LOWERCASE, UPPERCASE, DIGITS, SYMBOLS] {
+            assert!(generated.secret.chars().any(|c| class.contains(c)));
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::crypto::EncryptedVault;
+use crate::kdbx;
+use crate::migration;
+use crate::otp::OtpSecret;
+use crate::pass;
+use crate::vault::{self, CustomField, PasswordEntry, VaultDocument};
+
+/// Export formats `passman import` understands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Chrome,
+    Firefox,
+    Bitwarden,
+    LastPass,
+    OnePassword,
+    Kdbx,
+    Pass,
+    Passman,
+}
+
+impl ImportFormat {
+    /// Parses the value of `--format`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chrome" => Ok(Self::Chrome),
+            "firefox" => Ok(Self::Firefox),
+            "bitwarden" => Ok(Self::Bitwarden),
+            "lastpass" => Ok(Self::LastPass),
+            "1password" | "onepassword" => Ok(Self::OnePassword),
+            "kdbx" | "keepass" => Ok(Self::Kdbx),
+            "pass" | "password-store" => Ok(Self::Pass),
+            "passman" | "json" => Ok(Self::Passman),
+            other => Err(anyhow!(
+                "Unknown import format '{}' (expected chrome, firefox, bitwarden, lastpass, 1password, kdbx, pass or passman)",
+                other
+            )),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Chrome => "Chrome CSV",
+            Self::Firefox => "Firefox CSV",
+            Self::Bitwarden => "Bitwarden JSON",
+            Self::LastPass => "LastPass CSV",
+            Self::OnePassword => "1Password CSV",
+            Self::Kdbx => "KeePass KDBX 4",
+            Self::Pass => "password-store",
+            Self::Passman => "passman encrypted JSON",
+        }
+    }
+
+    /// Works out the format from the file's content: KeePass databases have
+    /// a signature, Bitwarden exports are JSON and the CSV exports are told
+    /// apart by their header row.
+    pub fn detect(data: &[u8]) -> Result<Self> {
+        if kdbx::is_kdbx(data) {
+            return Ok(Self::Kdbx);
+        }
+        let content = text_content(data)?;
+        if EncryptedVault::parse(content).is_some() {
+            return Ok(Self::Passman);
+        }
+        if content.trim_start().starts_with('{') {
+            return Ok(Self::Bitwarden);
+        }
+
+        let headers = csv_headers(content)?;
+        let has = |column: &str| headers.contains_key(column);
+        if has("httprealm") || has("formactionorigin") {
+            Ok(Self::Firefox)
+        } else if has("grouping") || has("extra") {
+            Ok(Self::LastPass)
+        } else if has("title") {
+            Ok(Self::OnePassword)
+        } else if has("name") && has("url") && has("username") && has("password") {
+            Ok(Self::Chrome)
+        } else {
+            Err(anyhow!("Could not recognise the file's format; pass --format to choose one"))
+        }
+    }
+}
+
+/// What to do with an imported entry whose name, username and URL match an
+/// entry already in the vault.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateStrategy {
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+impl DuplicateStrategy {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "rename" => Ok(Self::Rename),
+            "overwrite" => Ok(Self::Overwrite),
+            other => Err(anyhow!("Unknown duplicate strategy '{}' (expected skip, rename or overwrite)", other)),
+        }
+    }
+}
+
+/// Entries read from an export, plus descriptions of the records that could
+/// not be turned into entries.
+pub struct Imported {
+    pub format: ImportFormat,
+    pub entries: Vec<PasswordEntry>,
+    pub skipped: Vec<String>,
+}
+
+pub enum PlannedAction {
+    Add(PasswordEntry),
+    /// Added under a new name because the original was already taken
+    Rename { entry: PasswordEntry, original_name: String },
+    /// Replaces the existing entry with the same id
+    Overwrite(PasswordEntry),
+    Skip(PasswordEntry),
+}
+
+/// Reads an export file, detecting its format unless one is given.
+/// `password` is only asked for when the file is encrypted.
+pub fn read_file(path: &Path, format: Option<ImportFormat>, password: impl FnOnce() -> Result<String>) -> Result<Imported> {
+    // A password-store is a directory of files rather than one export
+    if format == Some(ImportFormat::Pass) || path.is_dir() {
+        let mut imported = Imported { format: ImportFormat::Pass, entries: Vec::new(), skipped: Vec::new() };
+        imported.entries = pass::read_store(path, &mut imported.skipped)?;
+        return Ok(imported);
+    }
+
+    let data = std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let format = match format {
+        Some(format) => format,
+        None => ImportFormat::detect(&data)?,
+    };
+
+    let mut imported = Imported { format, entries: Vec::new(), skipped: Vec::new() };
+    match format {
+        ImportFormat::Pass => unreachable!("handled above"),
+        ImportFormat::Kdbx => imported.entries = kdbx::read(&data, &password()?, &mut imported.skipped)?,
+        ImportFormat::Passman => imported.entries = read_passman(text_content(&data)?, &password()?)?,
+        ImportFormat::Bitwarden => read_bitwarden(text_content(&data)?, &mut imported)?,
+        _ => read_csv(text_content(&data)?, &mut imported)?,
+    }
+    Ok(imported)
+}
+
+/// The text of a JSON or CSV export, without a byte order mark.
+fn text_content(data: &[u8]) -> Result<&str> {
+    let content = std::str::from_utf8(data).map_err(|_| anyhow!("The file is not UTF-8 text; pass --format to choose a format"))?;
+    Ok(content.trim_start_matches('\u{feff}'))
+}
+
+/// Decides what happens to every imported entry, comparing against the vault
+/// and against entries earlier in the same import. Added entries get a new
+/// id when theirs is already in use, as when re-importing an export.
+pub fn plan(existing: &[PasswordEntry], imported: Vec<PasswordEntry>, strategy: DuplicateStrategy) -> Vec<PlannedAction> {
+    let mut taken: HashMap<DuplicateKey, Option<&PasswordEntry>> =
+        existing.iter().map(|entry| (duplicate_key(entry), Some(entry))).collect();
+    let mut names: HashSet<String> = existing.iter().map(|entry| entry.name.clone()).collect();
+    let mut ids: HashSet<String> = existing.iter().map(|entry| entry.id.clone()).collect();
+
+    let mut actions = Vec::with_capacity(imported.len());
+    for mut entry in imported {
+        let key = duplicate_key(&entry);
+        let Some(duplicate) = taken.get(&key).copied() else {
+            names.insert(entry.name.clone());
+            taken.insert(key, None);
+            claim_id(&mut entry, &mut ids);
+            actions.push(PlannedAction::Add(entry));
+            continue;
+        };
+
+        match (strategy, duplicate) {
+            (DuplicateStrategy::Overwrite, Some(current)) => {
+                entry.id = current.id.clone();
+                entry.created_at = current.created_at;
+                actions.push(PlannedAction::Overwrite(entry));
+            }
+            (DuplicateStrategy::Rename, _) => {
+                let original_name = entry.name.clone();
+                entry.name = (2..)
+                    .map(|n| format!("{} ({})", original_name, n))
+                    .find(|name| !names.contains(name))
+                    .expect("some numbered name is free");
+                names.insert(entry.name.clone());
+                taken.insert(duplicate_key(&entry), None);
+                claim_id(&mut entry, &mut ids);
+                actions.push(PlannedAction::Rename { entry, original_name });
+            }
+            // Duplicates within the import itself have nothing to overwrite
+            _ => actions.push(PlannedAction::Skip(entry)),
+        }
+    }
+    actions
+}
+
+/// Gives the entry a fresh id if another entry already has its own.
+fn claim_id(entry: &mut PasswordEntry, ids: &mut HashSet<String>) {
+    if ids.contains(&entry.id) {
+        entry.id = uuid::Uuid::new_v4().to_string();
+    }
+    ids.insert(entry.id.clone());
+}
+
+type DuplicateKey = (String, String, String);
+
+fn duplicate_key(entry: &PasswordEntry) -> DuplicateKey {
+    let normalize = |value: Option<&String>| value.map(|v| v.trim().to_lowercase()).unwrap_or_default();
+    (
+        entry.name.trim().to_lowercase(),
+        normalize(entry.username.as_ref()),
+        normalize(entry.url.as_ref()),
+    )
+}
+
+/// Maps lowercased header names to column indices.
+fn csv_headers(content: &str) -> Result<HashMap<String, usize>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(content.as_bytes());
+    let headers = reader.headers().context("Could not read the CSV header row")?;
+    Ok(headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| (header.trim().to_lowercase(), i))
+        .collect())
+}
+
+fn read_csv(content: &str, imported: &mut Imported) -> Result<()> {
+    let headers = csv_headers(content)?;
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(content.as_bytes());
+
+    for (row, record) in reader.records().enumerate() {
+        let record = record.with_context(|| format!("Could not read CSV row {}", row + 2))?;
+        let field = |column: &str| {
+            headers
+                .get(column)
+                .and_then(|&i| record.get(i))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        // Passwords are taken verbatim; surrounding spaces may be part of them
+        let password = headers
+            .get("password")
+            .and_then(|&i| record.get(i))
+            .filter(|value| !value.is_empty());
+        let description = format!("row {}", row + 2);
+
+        let (name, url, otp, notes, tags, folder) = match imported.format {
+            ImportFormat::Chrome => (field("name"), field("url"), None, field("note"), Vec::new(), None),
+            ImportFormat::Firefox => (None, field("url"), None, None, Vec::new(), None),
+            ImportFormat::LastPass => {
+                // LastPass exports secure notes with this placeholder URL
+                let url = field("url").filter(|url| url != "http://sn");
+                let folder = field("grouping").and_then(|group| normalize_folder(&group));
+                (field("name"), url, field("totp"), field("extra"), Vec::new(), folder)
+            }
+            ImportFormat::OnePassword => {
+                let tags = field("tags").map(|tags| vault::parse_tags(&tags)).unwrap_or_default();
+                let otp = field("otpauth").or_else(|| field("one-time password"));
+                (field("title"), field("url").or_else(|| field("website")), otp, field("notes"), tags, None)
+            }
+            ImportFormat::Bitwarden | ImportFormat::Kdbx | ImportFormat::Pass | ImportFormat::Passman => {
+                unreachable!("not a CSV format")
+            }
+        };
+
+        let Some(password) = password else {
+            imported.skipped.push(format!("{} ({}): no password", description, name.or(url).unwrap_or_default()));
+            continue;
+        };
+        let Some(name) = name.or_else(|| url.as_deref().map(name_from_url)) else {
+            imported.skipped.push(format!("{}: no name or URL", description));
+            continue;
+        };
+
+        let mut entry = PasswordEntry::new(&name, password);
+        entry.username = field("username");
+        entry.url = url;
+        entry.notes = notes;
+        entry.tags = tags;
+        entry.folder = folder;
+        entry.otp = parse_otp(otp.as_deref(), &name, &mut imported.skipped);
+
+        // Firefox records when each login was created and last changed
+        if imported.format == ImportFormat::Firefox {
+            if let Some(created) = field("timecreated").and_then(|ms| timestamp_millis(&ms)) {
+                entry.created_at = created;
+                entry.updated_at = created;
+            }
+            if let Some(changed) = field("timepasswordchanged").and_then(|ms| timestamp_millis(&ms)) {
+                entry.updated_at = changed;
+            }
+        }
+        imported.entries.push(entry);
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    folders: Vec<BitwardenFolder>,
+    #[serde(default)]
+    items: Vec<BitwardenItem>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenFolder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem {
+    name: String,
+    #[serde(default)]
+    folder_id: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    login: Option<BitwardenLogin>,
+    #[serde(default)]
+    fields: Vec<BitwardenField>,
+    #[serde(default)]
+    creation_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    revision_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    totp: Option<String>,
+    #[serde(default)]
+    uris: Vec<BitwardenUri>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenField {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default, rename = "type")]
+    kind: u8,
+}
+
+/// Bitwarden's field type for values hidden like a password
+const BITWARDEN_HIDDEN_FIELD: u8 = 1;
+
+fn read_bitwarden(content: &str, imported: &mut Imported) -> Result<()> {
+    let export: BitwardenExport = serde_json::from_str(content).context("Could not parse the Bitwarden export")?;
+    if export.encrypted {
+        return Err(anyhow!("This Bitwarden export is encrypted; export it again as unencrypted JSON"));
+    }
+    let folders: HashMap<String, String> = export.folders.into_iter().map(|f| (f.id, f.name)).collect();
+
+    for item in export.items {
+        let Some(login) = item.login else {
+            imported.skipped.push(format!("{}: not a login", item.name));
+            continue;
+        };
+        let Some(password) = login.password.filter(|p| !p.is_empty()) else {
+            imported.skipped.push(format!("{}: no password", item.name));
+            continue;
+        };
+
+        let mut entry = PasswordEntry::new(&item.name, &password);
+        entry.username = login.username.filter(|u| !u.is_empty());
+        entry.url = login.uris.into_iter().find_map(|uri| uri.uri.filter(|u| !u.is_empty()));
+        entry.folder = item
+            .folder_id
+            .and_then(|id| folders.get(&id))
+            .and_then(|folder| normalize_folder(folder));
+        entry.otp = parse_otp(login.totp.as_deref(), &item.name, &mut imported.skipped);
+        entry.notes = item.notes.filter(|notes| !notes.is_empty());
+        entry.custom_fields = item
+            .fields
+            .into_iter()
+            .filter_map(|field| {
+                Some(CustomField {
+                    name: field.name?,
+                    value: field.value.unwrap_or_default(),
+                    protected: field.kind == BITWARDEN_HIDDEN_FIELD,
+                })
+            })
+            .collect();
+
+        if let Some(created) = item.creation_date {
+            entry.created_at = created;
+        }
+        entry.updated_at = item.revision_date.unwrap_or(entry.created_at);
+        imported.entries.push(entry);
+    }
+    Ok(())
+}
+
+/// Opens a `passman export --format json` file, which uses the same
+/// container and document as the vault itself.
+fn read_passman(content: &str, password: &str) -> Result<Vec<PasswordEntry>> {
+    let container = EncryptedVault::parse(content).ok_or_else(|| anyhow!("Not a passman export"))?;
+    let (_, plaintext) = container
+        .open(password)
+        .map_err(|_| anyhow!("Wrong password for the export, or the file is damaged"))?;
+    let raw: serde_json::Value = serde_json::from_slice(&plaintext).context("The export is not valid JSON")?;
+    let document: VaultDocument = serde_json::from_value(migration::migrate(raw)?).context("Could not read the export")?;
+    Ok(document.entries)
+}
+
+/// Parses an OTP secret or URI, recording a note instead of failing the
+/// whole import if it can't be used.
+fn parse_otp(otp: Option<&str>, name: &str, skipped: &mut Vec<String>) -> Option<OtpSecret> {
+    let otp = otp.filter(|otp| !otp.trim().is_empty())?;
+    match OtpSecret::parse(otp) {
+        Ok(secret) => Some(secret),
+        Err(e) => {
+            skipped.push(format!("{}: one-time code not imported ({})", name, e));
+            None
+        }
+    }
+}
+
+/// Turns a folder path like `Work\Servers` into `Work/Servers`, or `None`
+/// if it is empty.
+pub fn normalize_folder(folder: &str) -> Option<String> {
+    let parts: Vec<&str> = folder
+        .split(['\\', '/'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+    Some(parts.join("/")).filter(|folder| !folder.is_empty())
+}
+
+/// Uses the host of a URL as the entry name, e.g. `github.com`.
+pub fn name_from_url(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(|host| host.trim_start_matches("www.").to_string()))
+        .unwrap_or_else(|| url.to_string())
+}
+
+fn timestamp_millis(value: &str) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(value.parse().ok()?).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, username: &str, url: &str) -> PasswordEntry {
+        let mut entry = PasswordEntry::new(name, "secret");
+        entry.username = Some(username.to_string());
+        entry.url = Some(url.to_string());
+        entry
+    }
+
+    fn summarize(actions: &[PlannedAction]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|action| match action {
+                PlannedAction::Add(entry) => format!("add {}", entry.name),
+                PlannedAction::Rename { entry, original_name } => format!("rename {} to {}", original_name, entry.name),
+                PlannedAction::Overwrite(entry) => format!("overwrite {}", entry.name),
+                PlannedAction::Skip(entry) => format!("skip {}", entry.name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_formats_from_their_content() {
+        let detect = |content: &str| ImportFormat::detect(content.as_bytes()).unwrap();
+        assert_eq!(detect("name,url,username,password\n"), ImportFormat::Chrome);
+        assert_eq!(detect("\u{feff}name,url,username,password,note\n"), ImportFormat::Chrome);
+        assert_eq!(
+            detect("\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\"\n"),
+            ImportFormat::Firefox
+        );
+        assert_eq!(detect("url,username,password,totp,extra,name,grouping,fav\n"), ImportFormat::LastPass);
+        assert_eq!(detect("Title,Website,Username,Password,OTPAuth,Notes\n"), ImportFormat::OnePassword);
+        assert_eq!(detect("{\"encrypted\": false, \"items\": []}"), ImportFormat::Bitwarden);
+        assert!(ImportFormat::detect(b"site,login,secret\n").is_err());
+        assert!(ImportFormat::detect(&[0xff, 0xfe, 0x00]).is_err());
+    }
+
+    #[test]
+    fn reads_csv_rows_and_notes_the_unusable_ones() {
+        let content = "url,username,password,totp,extra,name,grouping,fav\n\
+                       https://github.com,alice,hunter2,,,GitHub,Work\\Code,0\n\
+                       http://sn,,,,a secure note,Note,,0\n\
+                       https://www.example.com/login,bob,pw,,,,,0\n";
+        let mut imported = Imported { format: ImportFormat::LastPass, entries: Vec::new(), skipped: Vec::new() };
+        read_csv(content, &mut imported).unwrap();
+
+        let github = &imported.entries[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(github.username.as_deref(), Some("alice"));
+        assert_eq!(github.folder.as_deref(), Some("Work/Code"));
+        assert_eq!(imported.entries[1].name, "example.com");
+        assert_eq!(imported.skipped, vec!["row 3 (Note): no password"]);
+    }
+
+    #[test]
+    fn skips_duplicates_by_default() {
+        let existing = vec![entry("GitHub", "alice", "https://github.com")];
+        let imported = vec![
+            entry(" github ", "Alice", "https://github.com"),
+            entry("GitHub", "bob", "https://github.com"),
+        ];
+        let actions = plan(&existing, imported, DuplicateStrategy::Skip);
+        assert_eq!(summarize(&actions), vec!["skip  github ", "add GitHub"]);
+    }
+
+    #[test]
+    fn overwrites_the_matching_entry() {
+        let existing = vec![entry("GitHub", "alice", "https://github.com")];
+        let mut imported = entry("GitHub", "alice", "https://github.com");
+        imported.password = "new secret".to_string();
+        let actions = plan(&existing, vec![imported], DuplicateStrategy::Overwrite);
+
+        let [PlannedAction::Overwrite(entry)] = actions.as_slice() else { panic!("expected an overwrite") };
+        assert_eq!(entry.id, existing[0].id);
+        assert_eq!(entry.created_at, existing[0].created_at);
+        assert_eq!(entry.password, "new secret");
+    }
+
+    #[test]
+    fn renames_duplicates_to_a_free_name() {
+        let existing = vec![
+            entry("GitHub", "alice", "https://github.com"),
+            entry("GitHub (2)", "carol", "https://github.com"),
+        ];
+        let imported = vec![
+            entry("GitHub", "alice", "https://github.com"),
+            entry("GitHub", "alice", "https://github.com"),
+        ];
+        let actions = plan(&existing, imported, DuplicateStrategy::Rename);
+        assert_eq!(
+            summarize(&actions),
+            vec!["rename GitHub to GitHub (3)", "rename GitHub to GitHub (4)"]
+        );
+    }
+
+    #[test]
+    fn duplicates_within_the_import_have_nothing_to_overwrite() {
+        let imported = vec![
+            entry("GitHub", "alice", "https://github.com"),
+            entry("GitHub", "alice", "https://github.com"),
+        ];
+        let actions = plan(&[], imported, DuplicateStrategy::Overwrite);
+        assert_eq!(summarize(&actions), vec!["add GitHub", "skip GitHub"]);
+    }
+
+    #[test]
+    fn reimporting_an_export_adds_entries_under_new_ids() {
+        let dir = std::env::temp_dir().join(format!("passman-import-reimport-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = crate::config::Config { vault_path: dir.join("vault.json"), ..Default::default() };
+        let mut vault = crate::vault::VaultManager::new(&config).unwrap();
+        vault.unlock("master").unwrap();
+        vault.add_entry(entry("GitHub", "alice", "https://github.com")).unwrap();
+        vault.add_entry(entry("GitLab", "alice", "https://gitlab.com")).unwrap();
+
+        let export = dir.join("export.json");
+        std::fs::write(&export, crate::export::to_encrypted_json(vault.entries(), "export").unwrap()).unwrap();
+        let imported = read_file(&export, None, || Ok("export".to_string())).unwrap();
+        assert_eq!(imported.format, ImportFormat::Passman);
+
+        let actions = plan(vault.entries(), imported.entries, DuplicateStrategy::Rename);
+        assert_eq!(summarize(&actions), vec!["rename GitHub to GitHub (2)", "rename GitLab to GitLab (2)"]);
+        for action in actions {
+            let PlannedAction::Rename { entry, .. } = action else { unreachable!() };
+            vault.add_entry(entry).unwrap();
+        }
+        vault.save_entries().unwrap();
+        assert_eq!(vault.entries().len(), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
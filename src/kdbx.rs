@@ -0,0 +1,1072 @@
+//! KeePass KDBX 4 databases, as read and written by KeePass 2.35+ and
+//! KeePassXC 2.7+. Only password-protected databases are supported; key files
+//! and hardware keys are not.
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit, StreamCipher};
+use aes::Aes256;
+use anyhow::{anyhow, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chacha20::ChaCha20;
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rand::{rngs::OsRng, RngCore};
+use salsa20::Salsa20;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use zeroize::Zeroizing;
+
+use crate::otp::OtpSecret;
+use crate::vault::{CustomField, PasswordEntry};
+
+const SIGNATURE_1: u32 = 0x9AA2_D903;
+const SIGNATURE_2: u32 = 0xB54B_FB67;
+const VERSION_MAJOR: u32 = 4;
+
+const CIPHER_AES256: [u8; 16] = [
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+const CIPHER_CHACHA20: [u8; 16] = [
+    0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a,
+];
+const KDF_ARGON2D: [u8; 16] = [
+    0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c,
+];
+const KDF_ARGON2ID: [u8; 16] = [
+    0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6,
+];
+const KDF_AES: [u8; 16] = [
+    0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea,
+];
+
+// Outer header field ids
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER_ID: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_IV: u8 = 7;
+const HEADER_KDF_PARAMETERS: u8 = 11;
+
+// Inner header field ids
+const INNER_END: u8 = 0;
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+
+const STREAM_SALSA20: u32 = 2;
+const STREAM_CHACHA20: u32 = 3;
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+// VariantDictionary value types
+const VARIANT_VERSION: u16 = 0x0100;
+const VARIANT_U32: u8 = 0x04;
+const VARIANT_U64: u8 = 0x05;
+const VARIANT_BYTES: u8 = 0x42;
+
+/// Seconds between 0001-01-01, KeePass's epoch, and the Unix epoch
+const KEEPASS_EPOCH_OFFSET: i64 = 62_135_596_800;
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+// Exported databases use the same Argon2id cost as the vault itself
+const EXPORT_MEMORY_BYTES: u64 = 64 * 1024 * 1024;
+const EXPORT_ITERATIONS: u64 = 3;
+const EXPORT_PARALLELISM: u32 = 1;
+
+// Key derivation costs come from the file being imported, so anything past
+// what KeePass and KeePassXC would ever choose is refused rather than run
+const MAX_ARGON2_MEMORY_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+const MAX_ARGON2_ITERATIONS: u64 = 1000;
+const MAX_ARGON2_PARALLELISM: u32 = 64;
+const MAX_AES_ROUNDS: u64 = 100_000_000;
+
+/// Standard KeePass string fields that map onto entry fields rather than
+/// custom fields.
+const STANDARD_FIELDS: [&str; 5] = ["Title", "UserName", "Password", "URL", "Notes"];
+/// The field KeePassXC stores `otpauth://` URIs in.
+const OTP_FIELD: &str = "otp";
+
+/// Payload cipher of an exported database.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KdbxCipher {
+    Aes256,
+    ChaCha20,
+}
+
+impl KdbxCipher {
+    /// Parses the value of `--cipher`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "aes" | "aes256" => Ok(Self::Aes256),
+            "chacha20" => Ok(Self::ChaCha20),
+            other => Err(anyhow!("Unknown KDBX cipher '{}' (expected aes or chacha20)", other)),
+        }
+    }
+
+    fn uuid(&self) -> [u8; 16] {
+        match self {
+            Self::Aes256 => CIPHER_AES256,
+            Self::ChaCha20 => CIPHER_CHACHA20,
+        }
+    }
+
+    fn iv_len(&self) -> usize {
+        match self {
+            Self::Aes256 => 16,
+            Self::ChaCha20 => 12,
+        }
+    }
+
+    fn encrypt(&self, key: &[u8], iv: &[u8], mut data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Self::Aes256 => Ok(cbc::Encryptor::<Aes256>::new_from_slices(key, iv)
+                .map_err(|_| anyhow!("Invalid AES key or IV length"))?
+                .encrypt_padded_vec_mut::<Pkcs7>(&data)),
+            Self::ChaCha20 => {
+                ChaCha20::new_from_slices(key, iv)
+                    .map_err(|_| anyhow!("Invalid ChaCha20 key or nonce length"))?
+                    .apply_keystream(&mut data);
+                Ok(data)
+            }
+        }
+    }
+
+    fn decrypt(&self, key: &[u8], iv: &[u8], mut data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Self::Aes256 => cbc::Decryptor::<Aes256>::new_from_slices(key, iv)
+                .map_err(|_| anyhow!("Invalid AES key or IV length in the KDBX header"))?
+                .decrypt_padded_vec_mut::<Pkcs7>(&data)
+                .map_err(|_| anyhow!("The KDBX payload could not be decrypted")),
+            Self::ChaCha20 => {
+                ChaCha20::new_from_slices(key, iv)
+                    .map_err(|_| anyhow!("Invalid ChaCha20 key or nonce length in the KDBX header"))?
+                    .apply_keystream(&mut data);
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Whether the data starts with the KeePass 2 file signature.
+pub fn is_kdbx(data: &[u8]) -> bool {
+    data.len() >= 8
+        && data[..4] == SIGNATURE_1.to_le_bytes()
+        && data[4..8] == SIGNATURE_2.to_le_bytes()
+}
+
+/// Decrypts a KDBX 4 database and converts its entries. Groups become the
+/// entry's folder; recycled entries and entries without a password are
+/// listed in `skipped` instead.
+pub fn read(data: &[u8], password: &str, skipped: &mut Vec<String>) -> Result<Vec<PasswordEntry>> {
+    if !is_kdbx(data) {
+        return Err(anyhow!("Not a KeePass database"));
+    }
+    let mut cursor = Cursor::new(data);
+    cursor.take(8)?;
+    let version = cursor.u32()?;
+    if version >> 16 != VERSION_MAJOR {
+        return Err(anyhow!(
+            "Only KDBX 4 databases are supported, this one is version {}.{}; save it again with KeePassXC 2.7 or KeePass 2.35 or later",
+            version >> 16,
+            version & 0xffff
+        ));
+    }
+
+    let (mut cipher, mut compressed, mut master_seed, mut iv, mut kdf) = (None, false, None, None, None);
+    loop {
+        let id = cursor.u8()?;
+        let length = cursor.u32()? as usize;
+        let value = cursor.take(length)?;
+        match id {
+            HEADER_END => break,
+            HEADER_CIPHER_ID => {
+                cipher = Some(match value {
+                    v if v == CIPHER_AES256 => KdbxCipher::Aes256,
+                    v if v == CIPHER_CHACHA20 => KdbxCipher::ChaCha20,
+                    _ => return Err(anyhow!("The KDBX file uses an unsupported cipher (only AES-256 and ChaCha20 are)")),
+                })
+            }
+            HEADER_COMPRESSION => compressed = Cursor::new(value).u32()? == 1,
+            HEADER_MASTER_SEED => master_seed = Some(value),
+            HEADER_IV => iv = Some(value),
+            HEADER_KDF_PARAMETERS => kdf = Some(Kdf::parse(value)?),
+            _ => {}
+        }
+    }
+    let header = &data[..cursor.position];
+    let missing = |field: &str| anyhow!("The KDBX header has no {}", field);
+    let cipher = cipher.ok_or_else(|| missing("cipher"))?;
+    let master_seed = master_seed.ok_or_else(|| missing("master seed"))?;
+    let iv = iv.ok_or_else(|| missing("encryption IV"))?;
+    let kdf = kdf.ok_or_else(|| missing("key derivation parameters"))?;
+
+    if cursor.take(32)? != Sha256::digest(header).as_slice() {
+        return Err(anyhow!("The KDBX header is corrupted"));
+    }
+    let keys = Keys::derive(password, master_seed, &kdf)?;
+    if !keys.verify_block_hmac(u64::MAX, &[header], cursor.take(32)?) {
+        return Err(anyhow!("Wrong password for the KDBX file (key files are not supported)"));
+    }
+
+    // HMAC-authenticated blocks, ended by an empty one
+    let mut payload = Vec::new();
+    for index in 0u64.. {
+        let hmac = cursor.take(32)?;
+        let size_bytes = cursor.take(4)?;
+        let size = i32::from_le_bytes(size_bytes.try_into().expect("four bytes"));
+        let size = usize::try_from(size).map_err(|_| anyhow!("The KDBX file has a block of negative size"))?;
+        let block = cursor.take(size)?;
+        if !keys.verify_block_hmac(index, &[&index.to_le_bytes(), size_bytes, block], hmac) {
+            return Err(anyhow!("Block {} of the KDBX file is corrupted", index));
+        }
+        if size == 0 {
+            break;
+        }
+        payload.extend_from_slice(block);
+    }
+
+    let mut payload = cipher.decrypt(keys.encryption.as_slice(), iv, payload)?;
+    if compressed {
+        let mut inflated = Vec::new();
+        GzDecoder::new(payload.as_slice())
+            .read_to_end(&mut inflated)
+            .context("Could not decompress the KDBX payload")?;
+        payload = inflated;
+    }
+    let payload = Zeroizing::new(payload);
+
+    let mut inner = Cursor::new(&payload);
+    let (mut stream_id, mut stream_key) = (None, None);
+    loop {
+        let id = inner.u8()?;
+        let length = inner.u32()? as usize;
+        let value = inner.take(length)?;
+        match id {
+            INNER_END => break,
+            INNER_STREAM_ID => stream_id = Some(Cursor::new(value).u32()?),
+            INNER_STREAM_KEY => stream_key = Some(value),
+            // Attachments are not imported
+            _ => {}
+        }
+    }
+    let stream = InnerStream::new(
+        stream_id.ok_or_else(|| anyhow!("The KDBX inner header has no stream cipher"))?,
+        stream_key.ok_or_else(|| anyhow!("The KDBX inner header has no stream key"))?,
+    )?;
+    let xml = std::str::from_utf8(&payload[inner.position..]).context("The KDBX XML is not valid UTF-8")?;
+    XmlParser::new(stream, skipped).parse(xml)
+}
+
+/// Encrypts entries as a KDBX 4 database: Argon2id, gzip compression and a
+/// ChaCha20 inner stream protecting passwords and protected custom fields.
+pub fn write(entries: &[PasswordEntry], password: &str, cipher: KdbxCipher) -> Result<Vec<u8>> {
+    let mut master_seed = [0u8; 32];
+    let mut iv = vec![0u8; cipher.iv_len()];
+    let mut salt = [0u8; 32];
+    let mut stream_key = Zeroizing::new([0u8; 64]);
+    OsRng.fill_bytes(&mut master_seed);
+    OsRng.fill_bytes(&mut iv);
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(stream_key.as_mut_slice());
+
+    let kdf = Kdf::Argon2 {
+        algorithm: Algorithm::Argon2id,
+        salt: salt.to_vec(),
+        memory_bytes: EXPORT_MEMORY_BYTES,
+        iterations: EXPORT_ITERATIONS,
+        parallelism: EXPORT_PARALLELISM,
+        version: Version::V0x13,
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&SIGNATURE_1.to_le_bytes());
+    header.extend_from_slice(&SIGNATURE_2.to_le_bytes());
+    header.extend_from_slice(&(VERSION_MAJOR << 16).to_le_bytes());
+    write_field(&mut header, HEADER_CIPHER_ID, &cipher.uuid());
+    write_field(&mut header, HEADER_COMPRESSION, &1u32.to_le_bytes());
+    write_field(&mut header, HEADER_MASTER_SEED, &master_seed);
+    write_field(&mut header, HEADER_IV, &iv);
+    write_field(&mut header, HEADER_KDF_PARAMETERS, &kdf.to_bytes());
+    write_field(&mut header, HEADER_END, b"\r\n\r\n");
+
+    let keys = Keys::derive(password, &master_seed, &kdf)?;
+
+    let mut inner = Zeroizing::new(Vec::new());
+    write_field(&mut inner, INNER_STREAM_ID, &STREAM_CHACHA20.to_le_bytes());
+    write_field(&mut inner, INNER_STREAM_KEY, stream_key.as_slice());
+    write_field(&mut inner, INNER_END, &[]);
+    let mut stream = InnerStream::new(STREAM_CHACHA20, stream_key.as_slice())?;
+    inner.extend_from_slice(XmlWriter::new(&mut stream).database(entries).as_bytes());
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&inner)?;
+    let compressed = encoder.finish()?;
+    let payload = cipher.encrypt(keys.encryption.as_slice(), &iv, compressed)?;
+
+    let mut output = header.clone();
+    output.extend_from_slice(&Sha256::digest(&header));
+    output.extend_from_slice(&keys.block_hmac(u64::MAX, &[&header]));
+    let blocks = payload.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+    for (index, block) in (0u64..).zip(blocks) {
+        let size = (block.len() as i32).to_le_bytes();
+        output.extend_from_slice(&keys.block_hmac(index, &[&index.to_le_bytes(), &size, block]));
+        output.extend_from_slice(&size);
+        output.extend_from_slice(block);
+    }
+    Ok(output)
+}
+
+fn write_field(out: &mut Vec<u8>, id: u8, value: &[u8]) {
+    out.push(id);
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.position.checked_add(length).filter(|&end| end <= self.data.len());
+        let end = end.ok_or_else(|| anyhow!("The KDBX file is truncated"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().expect("two bytes")))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("four bytes")))
+    }
+}
+
+/// Key derivation settings from the header's KDF parameter dictionary.
+enum Kdf {
+    Argon2 {
+        algorithm: Algorithm,
+        salt: Vec<u8>,
+        memory_bytes: u64,
+        iterations: u64,
+        parallelism: u32,
+        version: Version,
+    },
+    Aes {
+        seed: Vec<u8>,
+        rounds: u64,
+    },
+}
+
+impl Kdf {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let values = parse_variant_dictionary(data)?;
+        let bytes = |key: &str| {
+            values
+                .get(key)
+                .cloned()
+                .ok_or_else(|| anyhow!("The KDBX key derivation parameters have no '{}'", key))
+        };
+        let number = |key: &str| -> Result<u64> {
+            let value = bytes(key)?;
+            match value.len() {
+                4 => Ok(u64::from(u32::from_le_bytes(value.try_into().expect("four bytes")))),
+                8 => Ok(u64::from_le_bytes(value.try_into().expect("eight bytes"))),
+                _ => Err(anyhow!("The KDBX key derivation parameter '{}' is not a number", key)),
+            }
+        };
+
+        let uuid = bytes("$UUID")?;
+        if uuid == KDF_AES {
+            let rounds = number("R")?;
+            if rounds > MAX_AES_ROUNDS {
+                return Err(anyhow!("The KDBX file asks for {} AES-KDF rounds, more than the {} allowed", rounds, MAX_AES_ROUNDS));
+            }
+            return Ok(Self::Aes { seed: bytes("S")?, rounds });
+        }
+        let algorithm = if uuid == KDF_ARGON2D {
+            Algorithm::Argon2d
+        } else if uuid == KDF_ARGON2ID {
+            Algorithm::Argon2id
+        } else {
+            return Err(anyhow!("The KDBX file uses an unsupported key derivation function"));
+        };
+        let version = match number("V")? {
+            0x10 => Version::V0x10,
+            0x13 => Version::V0x13,
+            other => return Err(anyhow!("Unsupported Argon2 version {:#x}", other)),
+        };
+        let memory_bytes = number("M")?;
+        if memory_bytes > MAX_ARGON2_MEMORY_BYTES {
+            return Err(anyhow!(
+                "The KDBX file asks for {} MiB of Argon2 memory, more than the {} MiB allowed",
+                memory_bytes / (1024 * 1024),
+                MAX_ARGON2_MEMORY_BYTES / (1024 * 1024)
+            ));
+        }
+        let iterations = number("I")?;
+        if iterations > MAX_ARGON2_ITERATIONS {
+            return Err(anyhow!(
+                "The KDBX file asks for {} Argon2 iterations, more than the {} allowed",
+                iterations,
+                MAX_ARGON2_ITERATIONS
+            ));
+        }
+        let parallelism = u32::try_from(number("P")?)
+            .ok()
+            .filter(|lanes| *lanes <= MAX_ARGON2_PARALLELISM)
+            .ok_or_else(|| anyhow!("The KDBX file asks for more than {} Argon2 lanes", MAX_ARGON2_PARALLELISM))?;
+        Ok(Self::Argon2 { algorithm, salt: bytes("S")?, memory_bytes, iterations, parallelism, version })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = VARIANT_VERSION.to_le_bytes().to_vec();
+        let mut item = |kind: u8, key: &str, value: &[u8]| {
+            out.push(kind);
+            out.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            out.extend_from_slice(key.as_bytes());
+            out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            out.extend_from_slice(value);
+        };
+        match self {
+            Self::Argon2 { algorithm, salt, memory_bytes, iterations, parallelism, version } => {
+                let uuid = if *algorithm == Algorithm::Argon2d { KDF_ARGON2D } else { KDF_ARGON2ID };
+                item(VARIANT_BYTES, "$UUID", &uuid);
+                item(VARIANT_BYTES, "S", salt);
+                item(VARIANT_U32, "P", &parallelism.to_le_bytes());
+                item(VARIANT_U64, "M", &memory_bytes.to_le_bytes());
+                item(VARIANT_U64, "I", &iterations.to_le_bytes());
+                item(VARIANT_U32, "V", &(*version as u32).to_le_bytes());
+            }
+            Self::Aes { seed, rounds } => {
+                item(VARIANT_BYTES, "$UUID", &KDF_AES);
+                item(VARIANT_BYTES, "S", seed);
+                item(VARIANT_U64, "R", &rounds.to_le_bytes());
+            }
+        }
+        out.push(0);
+        out
+    }
+
+    /// Transforms the composite key into the 32-byte master key material.
+    fn transform(&self, composite: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let mut key = Zeroizing::new(vec![0u8; 32]);
+        match self {
+            Self::Argon2 { algorithm, salt, memory_bytes, iterations, parallelism, version } => {
+                let memory_kib = u32::try_from(memory_bytes / 1024).map_err(|_| anyhow!("Argon2 memory cost is too large"))?;
+                let iterations = u32::try_from(*iterations).map_err(|_| anyhow!("Argon2 iteration count is too large"))?;
+                let params = Params::new(memory_kib, iterations, *parallelism, Some(32))
+                    .map_err(|e| anyhow!("Invalid Argon2 parameters in the KDBX header: {}", e))?;
+                Argon2::new(*algorithm, *version, params)
+                    .hash_password_into(composite, salt, &mut key)
+                    .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+            }
+            Self::Aes { seed, rounds } => {
+                let cipher = Aes256::new_from_slice(seed).map_err(|_| anyhow!("Invalid AES-KDF seed length"))?;
+                key.copy_from_slice(composite);
+                for _ in 0..*rounds {
+                    for block in key.chunks_mut(16) {
+                        cipher.encrypt_block(block.into());
+                    }
+                }
+                let hashed = Sha256::digest(key.as_slice());
+                key.copy_from_slice(&hashed);
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// Reads a KDBX VariantDictionary into raw little-endian values by key.
+fn parse_variant_dictionary(data: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+    let mut cursor = Cursor::new(data);
+    if cursor.u16()? >> 8 != VARIANT_VERSION >> 8 {
+        return Err(anyhow!("Unsupported KDBX parameter dictionary version"));
+    }
+    let mut values = HashMap::new();
+    loop {
+        let kind = cursor.u8()?;
+        if kind == 0 {
+            return Ok(values);
+        }
+        let key_length = cursor.u32()? as usize;
+        let key = String::from_utf8_lossy(cursor.take(key_length)?).into_owned();
+        let value_length = cursor.u32()? as usize;
+        values.insert(key, cursor.take(value_length)?.to_vec());
+    }
+}
+
+struct Keys {
+    encryption: Zeroizing<Vec<u8>>,
+    hmac_base: Zeroizing<Vec<u8>>,
+}
+
+impl Keys {
+    fn derive(password: &str, master_seed: &[u8], kdf: &Kdf) -> Result<Self> {
+        // The composite key hashes each key component; a password is the only one
+        let composite = Zeroizing::new(Sha256::digest(Sha256::digest(password.as_bytes())).to_vec());
+        let transformed = kdf.transform(&composite)?;
+
+        let encryption = Sha256::new().chain_update(master_seed).chain_update(transformed.as_slice()).finalize();
+        let hmac_base = Sha512::new()
+            .chain_update(master_seed)
+            .chain_update(transformed.as_slice())
+            .chain_update([1u8])
+            .finalize();
+        Ok(Self {
+            encryption: Zeroizing::new(encryption.to_vec()),
+            hmac_base: Zeroizing::new(hmac_base.to_vec()),
+        })
+    }
+
+    /// HMAC-SHA256 over `parts` with the key for block `index`; the header
+    /// uses index `u64::MAX`.
+    fn block_hmac(&self, index: u64, parts: &[&[u8]]) -> Vec<u8> {
+        self.block_mac(index, parts).finalize().into_bytes().to_vec()
+    }
+
+    /// Checks `tag` against [`Self::block_hmac`] in constant time.
+    fn verify_block_hmac(&self, index: u64, parts: &[&[u8]], tag: &[u8]) -> bool {
+        self.block_mac(index, parts).verify_slice(tag).is_ok()
+    }
+
+    fn block_mac(&self, index: u64, parts: &[&[u8]]) -> Hmac<Sha256> {
+        let key = Sha512::new().chain_update(index.to_le_bytes()).chain_update(self.hmac_base.as_slice()).finalize();
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+/// The keystream protected values in the XML are XORed with, consumed in
+/// document order.
+enum InnerStream {
+    ChaCha20(ChaCha20),
+    Salsa20(Salsa20),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<Self> {
+        match id {
+            STREAM_CHACHA20 => {
+                let hash = Sha512::digest(key);
+                Ok(Self::ChaCha20(
+                    ChaCha20::new_from_slices(&hash[..32], &hash[32..44]).expect("SHA-512 output is long enough"),
+                ))
+            }
+            STREAM_SALSA20 => Ok(Self::Salsa20(
+                Salsa20::new_from_slices(&Sha256::digest(key), &SALSA20_NONCE).expect("valid Salsa20 key and nonce"),
+            )),
+            other => Err(anyhow!("The KDBX file uses an unsupported inner stream cipher ({})", other)),
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            Self::ChaCha20(cipher) => cipher.apply_keystream(data),
+            Self::Salsa20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+#[derive(Default)]
+struct XmlGroup {
+    uuid: String,
+    name: String,
+}
+
+#[derive(Default)]
+struct XmlEntry {
+    uuid: Option<String>,
+    strings: Vec<(String, String, bool)>,
+    tags: Option<String>,
+    created: Option<DateTime<Utc>>,
+    modified: Option<DateTime<Utc>>,
+}
+
+/// Walks the database XML, decrypting protected values as it goes.
+struct XmlParser<'a> {
+    stream: InnerStream,
+    skipped: &'a mut Vec<String>,
+    path: Vec<String>,
+    groups: Vec<XmlGroup>,
+    entries: Vec<XmlEntry>,
+    key: String,
+    value: String,
+    protected: bool,
+    recycle_bin: Option<String>,
+    converted: Vec<PasswordEntry>,
+}
+
+impl<'a> XmlParser<'a> {
+    fn new(stream: InnerStream, skipped: &'a mut Vec<String>) -> Self {
+        Self {
+            stream,
+            skipped,
+            path: Vec::new(),
+            groups: Vec::new(),
+            entries: Vec::new(),
+            key: String::new(),
+            value: String::new(),
+            protected: false,
+            recycle_bin: None,
+            converted: Vec::new(),
+        }
+    }
+
+    fn parse(mut self, xml: &str) -> Result<Vec<PasswordEntry>> {
+        let mut reader = Reader::from_str(xml);
+        let mut text = String::new();
+        loop {
+            match reader.read_event().context("Could not parse the KDBX XML")? {
+                Event::Start(element) => {
+                    self.start(&element)?;
+                    text.clear();
+                }
+                Event::Empty(element) => {
+                    self.start(&element)?;
+                    self.end(String::new())?;
+                }
+                Event::Text(content) => text.push_str(&content.unescape().context("Invalid text in the KDBX XML")?),
+                Event::CData(content) => text.push_str(&String::from_utf8_lossy(&content)),
+                Event::End(_) => self.end(std::mem::take(&mut text))?,
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(self.converted)
+    }
+
+    fn start(&mut self, element: &BytesStart) -> Result<()> {
+        let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+        match name.as_str() {
+            "Group" => self.groups.push(XmlGroup::default()),
+            "Entry" => self.entries.push(XmlEntry::default()),
+            "Value" => {
+                self.protected = false;
+                for attribute in element.attributes() {
+                    let attribute = attribute.context("Invalid attribute in the KDBX XML")?;
+                    if attribute.key.as_ref() == b"Protected" && attribute.value.as_ref() == b"True" {
+                        self.protected = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.path.push(name);
+        Ok(())
+    }
+
+    fn end(&mut self, text: String) -> Result<()> {
+        let name = self.path.pop().unwrap_or_default();
+        let parent = self.path.last().map(String::as_str).unwrap_or_default();
+        match (parent, name.as_str()) {
+            ("Meta", "RecycleBinUUID") => self.recycle_bin = Some(text),
+            ("Group", "UUID") => self.groups.last_mut().expect("inside a group").uuid = text,
+            ("Group", "Name") => self.groups.last_mut().expect("inside a group").name = text,
+            ("Entry", "UUID") => self.entries.last_mut().expect("inside an entry").uuid = Some(text),
+            ("Entry", "Tags") => self.entries.last_mut().expect("inside an entry").tags = Some(text),
+            ("Times", "CreationTime") | ("Times", "LastModificationTime") if self.in_entry_times() => {
+                let time = parse_time(&text);
+                let entry = self.entries.last_mut().expect("inside an entry");
+                if name == "CreationTime" {
+                    entry.created = time;
+                } else {
+                    entry.modified = time;
+                }
+            }
+            ("String", "Key") => self.key = text,
+            ("String", "Value") => {
+                // Every protected value advances the keystream, including
+                // those in entry history, so none can be skipped
+                self.value = if self.protected && !text.is_empty() {
+                    let mut bytes = BASE64.decode(text.trim()).context("Invalid protected value in the KDBX XML")?;
+                    self.stream.apply(&mut bytes);
+                    String::from_utf8(bytes).context("A protected KDBX value is not valid UTF-8")?
+                } else {
+                    text
+                };
+            }
+            (_, "String") => {
+                if let Some(entry) = self.entries.last_mut() {
+                    entry.strings.push((
+                        std::mem::take(&mut self.key),
+                        std::mem::take(&mut self.value),
+                        self.protected,
+                    ));
+                }
+                self.protected = false;
+            }
+            (_, "Entry") => {
+                let entry = self.entries.pop().expect("inside an entry");
+                // Older versions of an entry are kept in its History
+                if parent != "History" {
+                    self.convert(entry);
+                }
+            }
+            (_, "Group") => {
+                self.groups.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn in_entry_times(&self) -> bool {
+        self.path.len() >= 2 && self.path[self.path.len() - 2] == "Entry"
+    }
+
+    fn convert(&mut self, item: XmlEntry) {
+        let mut strings: Vec<(String, String, bool)> = item.strings;
+        let mut take = |key: &str| {
+            let position = strings.iter().position(|(k, _, _)| k == key)?;
+            let (_, value, _) = strings.remove(position);
+            Some(value).filter(|value| !value.is_empty())
+        };
+        let title = take("Title");
+        let password = take("Password");
+        let username = take("UserName");
+        let url = take("URL");
+        let notes = take("Notes");
+        let otp = take(OTP_FIELD);
+
+        let label = title.clone().or_else(|| url.clone()).unwrap_or_else(|| "untitled entry".to_string());
+        if let Some(bin) = &self.recycle_bin {
+            if self.groups.iter().any(|group| &group.uuid == bin) {
+                self.skipped.push(format!("{}: in the recycle bin", label));
+                return;
+            }
+        }
+        let Some(password) = password else {
+            self.skipped.push(format!("{}: no password", label));
+            return;
+        };
+        let Some(name) = title.or_else(|| url.as_deref().map(crate::import::name_from_url)) else {
+            self.skipped.push(format!("{}: no title or URL", label));
+            return;
+        };
+
+        let mut entry = PasswordEntry::new(&name, &password);
+        if let Some(id) = item.uuid.as_deref().and_then(uuid_from_base64) {
+            entry.id = id;
+        }
+        entry.username = username;
+        entry.url = url;
+        entry.notes = notes;
+        // The root group is the database itself, not a folder
+        let folder: Vec<&str> = self.groups.iter().skip(1).map(|group| group.name.as_str()).collect();
+        entry.folder = crate::import::normalize_folder(&folder.join("/"));
+        entry.tags = item
+            .tags
+            .map(|tags| {
+                tags.split([',', ';'])
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if let Some(otp) = otp {
+            match OtpSecret::parse(&otp) {
+                Ok(secret) => entry.otp = Some(secret),
+                Err(e) => {
+                    self.skipped.push(format!("{}: one-time code kept as a custom field ({})", name, e));
+                    strings.push((OTP_FIELD.to_string(), otp, true));
+                }
+            }
+        }
+        entry.custom_fields = strings
+            .into_iter()
+            .map(|(name, value, protected)| CustomField { name, value, protected })
+            .collect();
+        if let Some(created) = item.created {
+            entry.created_at = created;
+        }
+        entry.updated_at = item.modified.unwrap_or(entry.created_at);
+        self.converted.push(entry);
+    }
+}
+
+/// KDBX 4 stores times as base64 seconds since 0001-01-01; older files used
+/// ISO 8601.
+fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(bytes) = BASE64.decode(text.trim()) {
+        let seconds = i64::from_le_bytes(bytes.try_into().ok()?);
+        return Utc.timestamp_opt(seconds - KEEPASS_EPOCH_OFFSET, 0).single();
+    }
+    DateTime::parse_from_rfc3339(text.trim()).ok().map(|time| time.with_timezone(&Utc))
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    BASE64.encode((time.timestamp() + KEEPASS_EPOCH_OFFSET).to_le_bytes())
+}
+
+fn uuid_from_base64(text: &str) -> Option<String> {
+    let bytes = BASE64.decode(text.trim()).ok()?;
+    uuid::Uuid::from_slice(&bytes).ok().map(|uuid| uuid.to_string())
+}
+
+fn uuid_to_base64(id: &str) -> String {
+    let uuid = uuid::Uuid::parse_str(id).unwrap_or_else(|_| uuid::Uuid::new_v4());
+    BASE64.encode(uuid.as_bytes())
+}
+
+/// Folder tree built from entry folders, in the order they first appear.
+#[derive(Default)]
+struct GroupNode<'e> {
+    children: Vec<(String, GroupNode<'e>)>,
+    entries: Vec<&'e PasswordEntry>,
+}
+
+impl<'e> GroupNode<'e> {
+    fn insert(&mut self, folder: &[&str], entry: &'e PasswordEntry) {
+        let Some((first, rest)) = folder.split_first() else {
+            self.entries.push(entry);
+            return;
+        };
+        let index = match self.children.iter().position(|(name, _)| name == first) {
+            Some(index) => index,
+            None => {
+                self.children.push((first.to_string(), GroupNode::default()));
+                self.children.len() - 1
+            }
+        };
+        self.children[index].1.insert(rest, entry);
+    }
+}
+
+/// Serializes entries as KeePass XML, encrypting protected values with the
+/// inner stream in document order.
+struct XmlWriter<'s> {
+    stream: &'s mut InnerStream,
+    out: String,
+    now: String,
+}
+
+impl<'s> XmlWriter<'s> {
+    fn new(stream: &'s mut InnerStream) -> Self {
+        Self { stream, out: String::new(), now: format_time(&Utc::now()) }
+    }
+
+    fn database(mut self, entries: &[PasswordEntry]) -> Zeroizing<String> {
+        let mut root = GroupNode::default();
+        for entry in entries {
+            let folder: Vec<&str> = entry.folder.as_deref().unwrap_or_default().split('/').filter(|p| !p.is_empty()).collect();
+            root.insert(&folder, entry);
+        }
+
+        self.out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>\n<Meta>\n");
+        self.element("Generator", "passman");
+        self.element("DatabaseName", "passman");
+        self.element("DatabaseNameChanged", &self.now.clone());
+        self.element("RecycleBinEnabled", "False");
+        self.out.push_str("</Meta>\n<Root>\n");
+        self.group("passman", &root);
+        self.out.push_str("</Root>\n</KeePassFile>\n");
+        Zeroizing::new(self.out)
+    }
+
+    fn group(&mut self, name: &str, node: &GroupNode) {
+        self.out.push_str("<Group>\n");
+        self.element("UUID", &BASE64.encode(uuid::Uuid::new_v4().as_bytes()));
+        self.element("Name", name);
+        self.element("IconID", "48");
+        self.times(&Utc::now(), &Utc::now());
+        self.element("IsExpanded", "True");
+        for entry in &node.entries {
+            self.entry(entry);
+        }
+        for (name, child) in &node.children {
+            self.group(name, child);
+        }
+        self.out.push_str("</Group>\n");
+    }
+
+    fn entry(&mut self, entry: &PasswordEntry) {
+        self.out.push_str("<Entry>\n");
+        self.element("UUID", &uuid_to_base64(&entry.id));
+        self.element("IconID", "0");
+        self.element("Tags", &entry.tags.join(";"));
+        self.times(&entry.created_at, &entry.updated_at);
+
+        self.string("Title", &entry.name, false);
+        self.string("UserName", entry.username.as_deref().unwrap_or_default(), false);
+        self.string("Password", &entry.password, true);
+        self.string("URL", entry.url.as_deref().unwrap_or_default(), false);
+        self.string("Notes", entry.notes.as_deref().unwrap_or_default(), false);
+        if let Some(otp) = &entry.otp {
+            self.string(OTP_FIELD, &otp.to_uri(&entry.name), true);
+        }
+        // KeePass rejects duplicate keys, so custom fields can't shadow the
+        // standard ones
+        let mut written: Vec<&str> = STANDARD_FIELDS.to_vec();
+        if entry.otp.is_some() {
+            written.push(OTP_FIELD);
+        }
+        for field in &entry.custom_fields {
+            if !written.contains(&field.name.as_str()) {
+                written.push(&field.name);
+                self.string(&field.name, &field.value, field.protected);
+            }
+        }
+        self.out.push_str("</Entry>\n");
+    }
+
+    fn times(&mut self, created: &DateTime<Utc>, modified: &DateTime<Utc>) {
+        self.out.push_str("<Times>\n");
+        self.element("CreationTime", &format_time(created));
+        self.element("LastModificationTime", &format_time(modified));
+        self.element("LastAccessTime", &format_time(modified));
+        self.element("ExpiryTime", &format_time(modified));
+        self.element("Expires", "False");
+        self.element("UsageCount", "0");
+        self.element("LocationChanged", &format_time(modified));
+        self.out.push_str("</Times>\n");
+    }
+
+    fn string(&mut self, key: &str, value: &str, protected: bool) {
+        self.out.push_str("<String>\n");
+        self.element("Key", key);
+        if protected {
+            let mut bytes = Zeroizing::new(value.as_bytes().to_vec());
+            self.stream.apply(&mut bytes);
+            self.out.push_str(&format!("<Value Protected=\"True\">{}</Value>\n", BASE64.encode(&bytes)));
+        } else {
+            self.element("Value", value);
+        }
+        self.out.push_str("</String>\n");
+    }
+
+    fn element(&mut self, name: &str, text: &str) {
+        self.out.push_str(&format!("<{}>{}</{}>\n", name, escape(text), name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{self, DuplicateStrategy, PlannedAction};
+
+    fn sample_entries() -> Vec<PasswordEntry> {
+        let mut github = PasswordEntry::new("GitHub", "hunter2 <&> \"quoted\"");
+        github.username = Some("alice".to_string());
+        github.url = Some("https://github.com".to_string());
+        github.notes = Some("Recovery codes\nin the safe".to_string());
+        github.folder = Some("Work/Code".to_string());
+        github.tags = vec!["work".to_string(), "dev".to_string()];
+        github.otp = Some(OtpSecret::parse("otpauth://hotp/GitHub?secret=JBSWY3DPEHPK3PXP&digits=8&counter=4").unwrap());
+        github.custom_fields = vec![
+            CustomField { name: "PIN".to_string(), value: "1234".to_string(), protected: true },
+            CustomField { name: "Account".to_string(), value: "42".to_string(), protected: false },
+        ];
+        vec![github, PasswordEntry::new("Router", "admin")]
+    }
+
+    #[test]
+    fn write_and_read_round_trip() {
+        let entries = sample_entries();
+        for cipher in [KdbxCipher::ChaCha20, KdbxCipher::Aes256] {
+            let data = write(&entries, "correct horse", cipher).unwrap();
+            assert!(is_kdbx(&data));
+
+            let mut skipped = Vec::new();
+            let read_back = read(&data, "correct horse", &mut skipped).unwrap();
+            assert!(skipped.is_empty(), "{:?}", skipped);
+            assert_eq!(read_back.len(), 2);
+
+            // Entries come back depth first, so the root group's come first
+            let (router, github) = (&read_back[0], &read_back[1]);
+            assert_eq!(router.name, "Router");
+            assert_eq!(router.folder, None);
+            assert_eq!(github.id, entries[0].id);
+            assert_eq!(github.password, entries[0].password);
+            assert_eq!(github.username.as_deref(), Some("alice"));
+            assert_eq!(github.notes, entries[0].notes);
+            assert_eq!(github.folder.as_deref(), Some("Work/Code"));
+            assert_eq!(github.tags, vec!["work", "dev"]);
+            assert_eq!(github.created_at.timestamp(), entries[0].created_at.timestamp());
+
+            let otp = github.otp.as_ref().unwrap();
+            assert_eq!((otp.secret.as_str(), otp.digits, otp.counter), ("JBSWY3DPEHPK3PXP", 8, Some(4)));
+            let fields: Vec<(&str, &str, bool)> = github
+                .custom_fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.value.as_str(), field.protected))
+                .collect();
+            assert_eq!(fields, vec![("PIN", "1234", true), ("Account", "42", false)]);
+        }
+    }
+
+    #[test]
+    fn rejects_the_wrong_password() {
+        let data = write(&sample_entries(), "correct horse", KdbxCipher::ChaCha20).unwrap();
+        let error = read(&data, "battery staple", &mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("password"), "{}", error);
+    }
+
+    #[test]
+    fn refuses_key_derivation_costs_past_the_caps() {
+        let argon2 = |memory_bytes, iterations, parallelism| Kdf::Argon2 {
+            algorithm: Algorithm::Argon2id,
+            salt: vec![7; 32],
+            memory_bytes,
+            iterations,
+            parallelism,
+            version: Version::V0x13,
+        };
+        let exported = argon2(EXPORT_MEMORY_BYTES, EXPORT_ITERATIONS, EXPORT_PARALLELISM);
+        assert!(Kdf::parse(&exported.to_bytes()).is_ok());
+
+        for kdf in [
+            argon2(MAX_ARGON2_MEMORY_BYTES + 1, EXPORT_ITERATIONS, EXPORT_PARALLELISM),
+            argon2(EXPORT_MEMORY_BYTES, u64::MAX, EXPORT_PARALLELISM),
+            argon2(EXPORT_MEMORY_BYTES, EXPORT_ITERATIONS, MAX_ARGON2_PARALLELISM + 1),
+            Kdf::Aes { seed: vec![7; 32], rounds: MAX_AES_ROUNDS + 1 },
+        ] {
+            let Err(error) = Kdf::parse(&kdf.to_bytes()) else { panic!("expected the cost to be refused") };
+            assert!(error.to_string().contains("allowed") || error.to_string().contains("lanes"), "{}", error);
+        }
+        assert!(Kdf::parse(&Kdf::Aes { seed: vec![7; 32], rounds: 60_000 }.to_bytes()).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_tampered_block() {
+        let mut data = write(&sample_entries(), "correct horse", KdbxCipher::ChaCha20).unwrap();
+        // The empty closing block is an HMAC and a size, so this is the payload's last byte
+        let last_payload_byte = data.len() - 32 - 4 - 1;
+        data[last_payload_byte] ^= 1;
+        let error = read(&data, "correct horse", &mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("corrupted"), "{}", error);
+    }
+
+    #[test]
+    fn importing_into_the_source_vault_does_not_reuse_ids() {
+        let entries = sample_entries();
+        let data = write(&entries, "correct horse", KdbxCipher::ChaCha20).unwrap();
+        let read_back = read(&data, "correct horse", &mut Vec::new()).unwrap();
+
+        for action in import::plan(&entries, read_back, DuplicateStrategy::Rename) {
+            let PlannedAction::Rename { entry, .. } = action else { panic!("expected a rename") };
+            assert!(entries.iter().all(|existing| existing.id != entry.id));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+
+/// Version written by this build. Bump it together with a new entry in
+/// `MIGRATIONS` whenever the vault document changes shape.
+pub const CURRENT_FORMAT_VERSION: u32 = 5;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+///
+/// - v0: bare JSON array of `{ name, password }` objects
+/// - v1: `{ format_version, entries }` with the rich entry schema
+/// - v2: the v1 document stored inside the encrypted container
+/// - v3: entries may carry an `otp` secret
+/// - v4: entries may carry a `folder` and `custom_fields`
+/// - v5: a `usage` map of recent uses by entry id, for frecency ranking
+const MIGRATIONS: [fn(Value) -> Result<Value>; CURRENT_FORMAT_VERSION as usize] = [
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+];
+
+/// Works out which format version a decoded vault document was written with.
+pub fn detect_version(document: &Value) -> Result<u32> {
+    match document {
+        Value::Array(_) => Ok(0),
+        Value::Object(fields) => {
+            let version = fields
+                .get("format_version")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| anyhow!("Vault document has no format_version"))?;
+            u32::try_from(version).map_err(|_| anyhow!("Invalid format_version {}", version))
+        }
+        _ => Err(anyhow!("Vault document is neither an array nor an object")),
+    }
+}
+
+/// Runs every migration needed to bring `document` up to the current version.
+pub fn migrate(mut document: Value) -> Result<Value> {
+    let version = detect_version(&document)?;
+    if version > CURRENT_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Vault format v{} was written by a newer passman (this build supports up to v{})",
+            version,
+            CURRENT_FORMAT_VERSION
+        ));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        document = migration(document)?;
+    }
+    Ok(document)
+}
+
+fn migrate_v0_to_v1(document: Value) -> Result<Value> {
+    let Value::Array(entries) = document else {
+        return Err(anyhow!("Expected a v0 vault to be a JSON array"));
+    };
+
+    let now = json!(chrono::Utc::now());
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let Value::Object(mut fields) = entry else {
+                return Err(anyhow!("Expected every v0 entry to be a JSON object"));
+            };
+            fill_missing(&mut fields, "id", || json!(uuid::Uuid::new_v4().to_string()));
+            fill_missing(&mut fields, "username", || Value::Null);
+            fill_missing(&mut fields, "url", || Value::Null);
+            fill_missing(&mut fields, "tags", || json!([]));
+            fill_missing(&mut fields, "notes", || Value::Null);
+            fill_missing(&mut fields, "created_at", || now.clone());
+            fill_missing(&mut fields, "updated_at", || now.clone());
+            Ok(Value::Object(fields))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(json!({ "format_version": 1, "entries": entries }))
+}
+
+// The document shape is unchanged; v2 only means it now lives inside the
+// encrypted container, which the save following a migration takes care of.
+fn migrate_v1_to_v2(mut document: Value) -> Result<Value> {
+    document["format_version"] = json!(2);
+    Ok(document)
+}
+
+// Entries without a one-time code need no changes, but older builds would
+// silently drop `otp` fields when saving, so they must refuse v3 vaults.
+fn migrate_v2_to_v3(mut document: Value) -> Result<Value> {
+    document["format_version"] = json!(3);
+    Ok(document)
+}
+
+// Like v3, an additive change that older builds must not save over.
+fn migrate_v3_to_v4(mut document: Value) -> Result<Value> {
+    document["format_version"] = json!(4);
+    Ok(document)
+}
+
+// Older builds would drop the usage history when saving.
+fn migrate_v4_to_v5(mut document: Value) -> Result<Value> {
+    document["format_version"] = json!(5);
+    Ok(document)
+}
+
+fn fill_missing(fields: &mut Map<String, Value>, key: &str, default: impl FnOnce() -> Value) {
+    fields.entry(key).or_insert_with(default);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::VaultDocument;
+
+    /// A document as each historical version wrote it, oldest first.
+    fn fixtures() -> Vec<Value> {
+        let entry = json!({
+            "id": "7d3c1f4e-0000-4000-8000-000000000001",
+            "name": "github",
+            "username": "alice",
+            "password": "hunter2",
+            "url": "https://github.com",
+            "tags": ["work"],
+            "notes": null,
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z"
+        });
+        let mut with_otp = entry.clone();
+        with_otp["otp"] = json!({ "secret": "JBSWY3DPEHPK3PXP", "algorithm": "SHA1", "digits": 6, "period": 30 });
+        let mut with_folder = with_otp.clone();
+        with_folder["folder"] = json!("Work/Code");
+        with_folder["custom_fields"] = json!([{ "name": "PIN", "value": "1234", "protected": true }]);
+
+        vec![
+            json!([{ "name": "github", "password": "hunter2" }]),
+            json!({ "format_version": 1, "entries": [entry] }),
+            json!({ "format_version": 2, "entries": [entry] }),
+            json!({ "format_version": 3, "entries": [with_otp] }),
+            json!({ "format_version": 4, "entries": [with_folder] }),
+        ]
+    }
+
+    #[test]
+    fn every_version_migrates_to_the_current_format() {
+        let fixtures = fixtures();
+        assert_eq!(fixtures.len(), MIGRATIONS.len());
+
+        for (version, fixture) in fixtures.into_iter().enumerate() {
+            assert_eq!(detect_version(&fixture).unwrap(), version as u32);
+            let migrated = migrate(fixture).unwrap();
+            let document: VaultDocument = serde_json::from_value(migrated)
+                .unwrap_or_else(|e| panic!("v{} did not migrate to a readable document: {}", version, e));
+
+            assert_eq!(document.format_version, CURRENT_FORMAT_VERSION);
+            let entry = &document.entries[0];
+            assert_eq!((entry.name.as_str(), entry.password.as_str()), ("github", "hunter2"));
+            if version >= 3 {
+                assert!(entry.otp.is_some());
+            }
+            if version >= 4 {
+                assert_eq!(entry.folder.as_deref(), Some("Work/Code"));
+                assert_eq!(entry.custom_fields[0].value, "1234");
+            }
+        }
+    }
+
+    #[test]
+    fn current_documents_are_left_alone() {
+        let document = serde_json::to_value(VaultDocument::default()).unwrap();
+        assert_eq!(migrate(document.clone()).unwrap(), document);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let document = json!({ "format_version": CURRENT_FORMAT_VERSION + 1, "entries": [] });
+        assert!(migrate(document).is_err());
+        assert!(migrate(json!("not a vault")).is_err());
+    }
+}
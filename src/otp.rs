@@ -0,0 +1,311 @@
+use anyhow::{anyhow, Result};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const BASE32_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Code lengths authenticators use; longer ones overflow the truncated HMAC
+const DIGITS: std::ops::RangeInclusive<u32> = 6..=10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        }
+    }
+}
+
+/// A one-time code generator attached to an entry: TOTP (RFC 6238) unless
+/// `counter` is set, in which case it's counter-based HOTP (RFC 4226).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtpSecret {
+    /// Shared key, base32 encoded as authenticator apps display it
+    pub secret: String,
+    pub algorithm: OtpAlgorithm,
+    // Checked when read, as vaults and imports may have been edited by hand
+    #[serde(deserialize_with = "deserialize_digits")]
+    pub digits: u32,
+    /// Seconds each TOTP code is valid for
+    #[serde(deserialize_with = "deserialize_period")]
+    pub period: u64,
+    /// Next HOTP counter value; advanced every time a code is used
+    #[serde(default)]
+    pub counter: Option<u64>,
+}
+
+/// A code and, for TOTP, how many seconds it stays valid.
+pub struct OtpCode {
+    pub code: String,
+    pub remaining_seconds: Option<u64>,
+}
+
+impl OtpSecret {
+    /// A TOTP secret with the usual defaults: SHA-1, 6 digits, 30 seconds.
+    pub fn new(secret: &str) -> Result<Self> {
+        Self::with_settings(secret, OtpAlgorithm::Sha1, 6, 30)
+    }
+
+    fn with_settings(secret: &str, algorithm: OtpAlgorithm, digits: u32, period: u64) -> Result<Self> {
+        let secret = normalize_base32(secret);
+        base32_decode(&secret)?;
+        Ok(Self {
+            secret,
+            algorithm,
+            digits: check_digits(digits)?,
+            period: check_period(period)?,
+            counter: None,
+        })
+    }
+
+    /// Accepts either an `otpauth://` URI or a bare base32 secret.
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if input.to_ascii_lowercase().starts_with("otpauth://") {
+            Self::from_uri(input)
+        } else {
+            Self::new(input)
+        }
+    }
+
+    /// Parses a Key Uri Format URI, e.g.
+    /// `otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP&issuer=GitHub`.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri).map_err(|e| anyhow!("Invalid otpauth URI: {}", e))?;
+        if url.scheme() != "otpauth" {
+            return Err(anyhow!("Expected an otpauth:// URI"));
+        }
+
+        let mut secret = None;
+        let mut counter = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+        for (key, value) in url.query_pairs() {
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(value.into_owned()),
+                "algorithm" => {
+                    algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => OtpAlgorithm::Sha1,
+                        "SHA256" => OtpAlgorithm::Sha256,
+                        "SHA512" => OtpAlgorithm::Sha512,
+                        other => return Err(anyhow!("Unsupported OTP algorithm '{}'", other)),
+                    }
+                }
+                "digits" => digits = value.parse().map_err(|_| anyhow!("Invalid OTP digits '{}'", value))?,
+                "period" => period = value.parse().map_err(|_| anyhow!("Invalid OTP period '{}'", value))?,
+                "counter" => counter = Some(value.parse().map_err(|_| anyhow!("Invalid HOTP counter '{}'", value))?),
+                _ => {}
+            }
+        }
+
+        let secret = secret.ok_or_else(|| anyhow!("The otpauth URI has no secret"))?;
+        let mut otp = Self::with_settings(&secret, algorithm, digits, period)?;
+        match url.host_str().map(str::to_ascii_lowercase).as_deref() {
+            Some("totp") => {}
+            Some("hotp") => otp.counter = Some(counter.ok_or_else(|| anyhow!("An HOTP URI needs a counter"))?),
+            _ => return Err(anyhow!("Expected otpauth://totp/ or otpauth://hotp/")),
+        }
+        Ok(otp)
+    }
+
+    /// The Key Uri Format form of this secret, as stored by KeePassXC.
+    pub fn to_uri(&self, label: &str) -> String {
+        let kind = if self.is_hotp() { "hotp" } else { "totp" };
+        let mut url = Url::parse(&format!("otpauth://{}/", kind)).expect("otpauth base URI is valid");
+        url.path_segments_mut()
+            .expect("otpauth URIs have a path")
+            .pop_if_empty()
+            .push(label);
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("secret", &self.secret);
+            query.append_pair("algorithm", self.algorithm.name());
+            query.append_pair("digits", &self.digits.to_string());
+            match self.counter {
+                Some(counter) => query.append_pair("counter", &counter.to_string()),
+                None => query.append_pair("period", &self.period.to_string()),
+            };
+        }
+        url.to_string()
+    }
+
+    pub fn is_hotp(&self) -> bool {
+        self.counter.is_some()
+    }
+
+    /// The code to use now. HOTP codes consume the counter, so callers must
+    /// save the entry afterwards.
+    pub fn next_code(&mut self) -> Result<OtpCode> {
+        if let Some(counter) = self.counter {
+            let code = self.code_for_counter(counter)?;
+            self.counter = Some(counter + 1);
+            return Ok(OtpCode { code, remaining_seconds: None });
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(OtpCode {
+            code: self.code_for_counter(now / self.period)?,
+            remaining_seconds: Some(self.period - now % self.period),
+        })
+    }
+
+    fn code_for_counter(&self, counter: u64) -> Result<String> {
+        let key = base32_decode(&self.secret)?;
+        let message = counter.to_be_bytes();
+        let digest = match self.algorithm {
+            OtpAlgorithm::Sha1 => hmac_digest::<Hmac<Sha1>>(&key, &message),
+            OtpAlgorithm::Sha256 => hmac_digest::<Hmac<Sha256>>(&key, &message),
+            OtpAlgorithm::Sha512 => hmac_digest::<Hmac<Sha512>>(&key, &message),
+        };
+
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+            & 0x7fff_ffff;
+        let code = u64::from(binary) % 10u64.pow(self.digits);
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+}
+
+fn check_digits(digits: u32) -> Result<u32> {
+    if DIGITS.contains(&digits) {
+        Ok(digits)
+    } else {
+        Err(anyhow!("OTP codes must have {} to {} digits, not {}", DIGITS.start(), DIGITS.end(), digits))
+    }
+}
+
+fn check_period(period: u64) -> Result<u64> {
+    if period == 0 {
+        return Err(anyhow!("The OTP period must be at least one second"));
+    }
+    Ok(period)
+}
+
+fn deserialize_digits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    check_digits(u32::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_period<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    check_period(u64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn hmac_digest<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Uppercases and strips the spaces, dashes and padding that authenticator
+/// setup pages like to add.
+fn normalize_base32(secret: &str) -> String {
+    secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn base32_decode(secret: &str) -> Result<Vec<u8>> {
+    if secret.is_empty() {
+        return Err(anyhow!("The OTP secret is empty"));
+    }
+
+    let mut bytes = Vec::with_capacity(secret.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in secret.chars() {
+        let value = BASE32_ALPHABET
+            .find(c)
+            .ok_or_else(|| anyhow!("The OTP secret is not valid base32 (unexpected '{}')", c))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "12345678901234567890" and its 32 and 64 byte repetitions, base32 encoded
+    const SHA1_KEY: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SHA256_KEY: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA====";
+    const SHA512_KEY: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA=";
+
+    #[test]
+    fn hotp_matches_rfc_4226_appendix_d() {
+        let expected = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        let mut otp = OtpSecret::from_uri(&format!("otpauth://hotp/Test?secret={}&counter=0", SHA1_KEY)).unwrap();
+        for code in expected {
+            let next = otp.next_code().unwrap();
+            assert_eq!(next.code, code);
+            assert_eq!(next.remaining_seconds, None);
+        }
+        assert_eq!(otp.counter, Some(10));
+    }
+
+    #[test]
+    fn totp_matches_rfc_6238_appendix_b() {
+        let vectors: [(u64, [&str; 3]); 6] = [
+            (59, ["94287082", "46119246", "90693936"]),
+            (1111111109, ["07081804", "68084774", "25091201"]),
+            (1111111111, ["14050471", "67062674", "99943326"]),
+            (1234567890, ["89005924", "91819424", "93441116"]),
+            (2000000000, ["69279037", "90698825", "38618901"]),
+            (20000000000, ["65353130", "77737706", "47863826"]),
+        ];
+        let secrets = [
+            (OtpAlgorithm::Sha1, SHA1_KEY),
+            (OtpAlgorithm::Sha256, SHA256_KEY),
+            (OtpAlgorithm::Sha512, SHA512_KEY),
+        ]
+        .map(|(algorithm, key)| OtpSecret::with_settings(key, algorithm, 8, 30).unwrap());
+
+        for (time, codes) in vectors {
+            for (otp, code) in secrets.iter().zip(codes) {
+                assert_eq!(otp.code_for_counter(time / otp.period).unwrap(), code, "{} at {}", otp.algorithm.name(), time);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unusable_digits_and_periods() {
+        assert!(OtpSecret::parse(&format!("otpauth://totp/Test?secret={}&digits=5", SHA1_KEY)).is_err());
+        assert!(OtpSecret::parse(&format!("otpauth://totp/Test?secret={}&digits=11", SHA1_KEY)).is_err());
+        assert!(OtpSecret::parse(&format!("otpauth://totp/Test?secret={}&period=0", SHA1_KEY)).is_err());
+        assert!(OtpSecret::parse(&format!("otpauth://totp/Test?secret={}&digits=10&period=60", SHA1_KEY)).is_ok());
+    }
+
+    #[test]
+    fn rejects_unusable_digits_and_periods_when_read_from_a_vault() {
+        let read = |digits: u32, period: u64| {
+            serde_json::from_str::<OtpSecret>(&format!(
+                r#"{{"secret":"{}","algorithm":"SHA1","digits":{},"period":{}}}"#,
+                SHA1_KEY, digits, period
+            ))
+        };
+        assert!(read(6, 30).is_ok());
+        assert!(read(20, 30).is_err());
+        assert!(read(6, 0).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+//! The `pass` (password-store) layout: one file per entry at
+//! `<store>/<folder>/<name>.gpg`, holding the password on the first line and
+//! `key: value` fields after it.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+use crate::import;
+use crate::otp::OtpSecret;
+use crate::vault::{self, CustomField, PasswordEntry};
+
+/// Lists the GPG key ids a store's files are encrypted for.
+pub const GPG_ID_FILE: &str = ".gpg-id";
+const ENTRY_EXTENSION: &str = "gpg";
+
+/// Field names other tools write for the standard entry fields. The first
+/// one of each is what the exporter uses.
+const USERNAME_KEYS: [&str; 4] = ["login", "username", "user", "email"];
+const URL_KEYS: [&str; 3] = ["url", "website", "site"];
+const TAGS_KEY: &str = "tags";
+
+/// Reads every entry in a store. `.gpg` files are decrypted with `gpg`, so
+/// the keys must be available locally; files already in plain text, with
+/// or without the extension, are read as they are.
+pub fn read_store(store: &Path, skipped: &mut Vec<String>) -> Result<Vec<PasswordEntry>> {
+    if !store.is_dir() {
+        return Err(anyhow!("{} is not a password-store directory", store.display()));
+    }
+
+    let mut files = Vec::new();
+    collect_files(store, &mut files)?;
+    files.sort();
+
+    let mut entries = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(store).unwrap_or(&file);
+        let label = relative.with_extension("").display().to_string();
+        let content = match read_entry_file(&file) {
+            Ok(content) => content,
+            Err(e) => {
+                skipped.push(format!("{}: {}", label, e));
+                continue;
+            }
+        };
+        match parse_entry(relative, &content, skipped) {
+            Some(entry) => entries.push(entry),
+            None => skipped.push(format!("{}: no password", label)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Writes each entry to `<store>/<folder>/<name>.gpg`, encrypted for
+/// `recipients` (or the store's `.gpg-id` when none are given). Entries the
+/// layout can't hold are listed in `skipped`. Returns the number of files
+/// written.
+pub fn write_store(store: &Path, entries: &[PasswordEntry], recipients: &[String], skipped: &mut Vec<String>) -> Result<usize> {
+    let recipients = if recipients.is_empty() {
+        read_gpg_ids(store)?
+    } else {
+        recipients.to_vec()
+    };
+    if recipients.is_empty() {
+        return Err(anyhow!(
+            "No GPG key to encrypt for: pass --recipient <key id> or add a {} file to the store",
+            GPG_ID_FILE
+        ));
+    }
+
+    create_private_dir(store)?;
+    let gpg_id_path = store.join(GPG_ID_FILE);
+    if !gpg_id_path.exists() {
+        vault::write_private_file(&gpg_id_path, format!("{}\n", recipients.join("\n")).as_bytes())?;
+    }
+
+    let mut written = HashSet::new();
+    for entry in entries {
+        // Only the first line of the file is the password
+        if entry.password.contains(['\n', '\r']) {
+            skipped.push(format!("{}: the password has several lines", entry.name));
+            continue;
+        }
+        let path = unique_path(store, entry, &written);
+        if let Some(parent) = path.parent() {
+            create_private_dir(parent)?;
+        }
+        let encrypted = gpg_encrypt(format_entry(entry).as_bytes(), &recipients)
+            .with_context(|| format!("Could not encrypt '{}'", entry.name))?;
+        vault::write_private_file(&path, &encrypted).with_context(|| format!("Could not write {}", path.display()))?;
+        written.insert(path);
+    }
+    Ok(written.len())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for item in fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))? {
+        let path = item?.path();
+        // Skips .git, .gpg-id and other bookkeeping
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_entry_file(path: &Path) -> Result<Zeroizing<String>> {
+    let data = Zeroizing::new(fs::read(path).context("could not read the file")?);
+    // Binary OpenPGP packets always have the top bit of the first byte set
+    let encrypted = data.first().is_some_and(|byte| byte & 0x80 != 0) || data.starts_with(b"-----BEGIN PGP MESSAGE-----");
+    let plaintext = if encrypted { gpg_decrypt(path)? } else { data };
+    String::from_utf8(plaintext.to_vec())
+        .map(Zeroizing::new)
+        .map_err(|_| anyhow!("not UTF-8 text"))
+}
+
+/// Turns `Email/gmail.com` plus its content into an entry.
+fn parse_entry(relative: &Path, content: &str, skipped: &mut Vec<String>) -> Option<PasswordEntry> {
+    let mut lines = content.lines();
+    let password = lines.next().filter(|password| !password.is_empty())?;
+    let name = match relative.extension().and_then(|ext| ext.to_str()) {
+        Some(ENTRY_EXTENSION) | Some("txt") => relative.file_stem(),
+        _ => relative.file_name(),
+    }?
+    .to_string_lossy()
+    .into_owned();
+
+    let mut entry = PasswordEntry::new(&name, password);
+    entry.folder = relative
+        .parent()
+        .and_then(|parent| import::normalize_folder(&parent.to_string_lossy()));
+
+    // `key: value` fields run until the first line that isn't one; the rest
+    // of the file is free-form notes
+    let mut notes = Vec::new();
+    for line in lines.by_ref() {
+        if line.trim().to_ascii_lowercase().starts_with("otpauth://") {
+            match OtpSecret::parse(line) {
+                Ok(otp) => entry.otp = Some(otp),
+                Err(e) => skipped.push(format!("{}: one-time code not imported ({})", name, e)),
+            }
+            continue;
+        }
+        // A bare link on its own line is the usual way to record the site
+        if (line.starts_with("http://") || line.starts_with("https://")) && entry.url.is_none() {
+            entry.url = Some(line.trim().to_string());
+            continue;
+        }
+        let Some((key, value)) = line
+            .split_once(':')
+            .filter(|(key, value)| is_field_name(key) && !value.starts_with("//"))
+        else {
+            if !line.trim().is_empty() {
+                notes.push(line);
+            }
+            break;
+        };
+        let value = value.trim();
+        let lowered = key.trim().to_ascii_lowercase();
+        if USERNAME_KEYS.contains(&lowered.as_str()) && entry.username.is_none() {
+            entry.username = Some(value.to_string()).filter(|v| !v.is_empty());
+        } else if URL_KEYS.contains(&lowered.as_str()) && entry.url.is_none() {
+            entry.url = Some(value.to_string()).filter(|v| !v.is_empty());
+        } else if lowered == TAGS_KEY {
+            entry.tags = vault::parse_tags(value);
+        } else {
+            entry.custom_fields.push(CustomField {
+                name: key.trim().to_string(),
+                value: value.to_string(),
+                protected: false,
+            });
+        }
+    }
+    notes.extend(lines);
+    let notes = notes.join("\n");
+    entry.notes = Some(notes.trim().to_string()).filter(|notes| !notes.is_empty());
+    Some(entry)
+}
+
+/// A field name is a short label, not a sentence that happens to contain a
+/// colon.
+fn is_field_name(key: &str) -> bool {
+    let key = key.trim();
+    !key.is_empty() && key.len() <= 40 && key.split_whitespace().count() <= 3
+}
+
+/// The file content for an entry, in the layout `parse_entry` reads.
+fn format_entry(entry: &PasswordEntry) -> Zeroizing<String> {
+    let mut content = Zeroizing::new(format!("{}\n", entry.password));
+    if let Some(username) = &entry.username {
+        content.push_str(&format!("{}: {}\n", USERNAME_KEYS[0], username));
+    }
+    if let Some(url) = &entry.url {
+        content.push_str(&format!("{}: {}\n", URL_KEYS[0], url));
+    }
+    if !entry.tags.is_empty() {
+        content.push_str(&format!("{}: {}\n", TAGS_KEY, entry.tags.join(", ")));
+    }
+    for field in &entry.custom_fields {
+        // Multi-line values can't be stored as a field line
+        let value = field.value.replace('\n', " ");
+        content.push_str(&format!("{}: {}\n", field.name, value));
+    }
+    // pass-otp keeps the URI on a line of its own
+    if let Some(otp) = &entry.otp {
+        content.push_str(&format!("{}\n", otp.to_uri(&entry.name)));
+    }
+    if let Some(notes) = &entry.notes {
+        content.push_str(&format!("\n{}\n", notes));
+    }
+    content
+}
+
+/// `<store>/<folder>/<name>.gpg`, numbered if another entry already took it.
+fn unique_path(store: &Path, entry: &PasswordEntry, taken: &HashSet<PathBuf>) -> PathBuf {
+    let mut dir = store.to_path_buf();
+    for part in entry.folder.as_deref().unwrap_or_default().split('/') {
+        let part = sanitize_component(part);
+        if !part.is_empty() {
+            dir.push(part);
+        }
+    }
+    let name = Some(sanitize_component(&entry.name))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "entry".to_string());
+
+    let path = dir.join(format!("{}.{}", name, ENTRY_EXTENSION));
+    if !taken.contains(&path) {
+        return path;
+    }
+    (2..)
+        .map(|n| dir.join(format!("{} ({}).{}", name, n, ENTRY_EXTENSION)))
+        .find(|path| !taken.contains(path))
+        .expect("some numbered name is free")
+}
+
+/// Keeps a name to a single path component that isn't hidden.
+fn sanitize_component(name: &str) -> String {
+    name.replace(['/', '\\', '\0'], "-").trim().trim_start_matches('.').to_string()
+}
+
+fn read_gpg_ids(store: &Path) -> Result<Vec<String>> {
+    let path = store.join(GPG_ID_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path).with_context(|| format!("Could not read {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect())
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir).with_context(|| format!("Could not create {}", dir.display()))
+}
+
+/// Same options `pass` itself uses, so gpg-agent handles passphrases.
+fn gpg_decrypt(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    let output = Command::new("gpg")
+        .args(["--quiet", "--yes", "--batch", "--use-agent", "--decrypt"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .context("could not run gpg")?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("gpg could not decrypt it ({})", message.trim()));
+    }
+    Ok(Zeroizing::new(output.stdout))
+}
+
+/// Encrypts with the plaintext on stdin so it never appears in a process
+/// listing or a temporary file.
+fn gpg_encrypt(plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+    let mut command = Command::new("gpg");
+    command.args(["--quiet", "--yes", "--batch", "--use-agent", "--compress-algo=none", "--no-encrypt-to", "--encrypt"]);
+    for recipient in recipients {
+        command.args(["--recipient", recipient]);
+    }
+    let mut child = command
+        .args(["--output", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Could not run gpg")?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(plaintext)?;
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("gpg failed: {}", message.trim()));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_and_parse_round_trip() {
+        let mut entry = PasswordEntry::new("gmail.com", " spaced: password ");
+        entry.folder = Some("Email/Personal".to_string());
+        entry.username = Some("alice@example.com".to_string());
+        entry.url = Some("https://mail.google.com".to_string());
+        entry.tags = vec!["mail".to_string(), "personal".to_string()];
+        entry.custom_fields = vec![CustomField { name: "Recovery".to_string(), value: "bob@example.com".to_string(), protected: false }];
+        entry.otp = Some(OtpSecret::new("JBSWY3DPEHPK3PXP").unwrap());
+        entry.notes = Some("Security question: first pet\nRex".to_string());
+
+        let mut skipped = Vec::new();
+        let parsed = parse_entry(Path::new("Email/Personal/gmail.com.gpg"), &format_entry(&entry), &mut skipped).unwrap();
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        assert_eq!(parsed.name, entry.name);
+        assert_eq!(parsed.password, entry.password);
+        assert_eq!(parsed.folder, entry.folder);
+        assert_eq!(parsed.username, entry.username);
+        assert_eq!(parsed.url, entry.url);
+        assert_eq!(parsed.tags, entry.tags);
+        assert_eq!(parsed.custom_fields[0].name, "Recovery");
+        assert_eq!(parsed.custom_fields[0].value, "bob@example.com");
+        assert_eq!(parsed.otp.unwrap().secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(parsed.notes, entry.notes);
+    }
+
+    #[test]
+    fn skips_passwords_with_several_lines() {
+        let store = std::env::temp_dir().join(format!("passman-pass-multiline-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&store);
+        let entries = [PasswordEntry::new("router", "first line\nsecond line"), PasswordEntry::new("modem", "line\r")];
+
+        let mut skipped = Vec::new();
+        let written = write_store(&store, &entries, &["nobody@example.com".to_string()], &mut skipped).unwrap();
+        assert_eq!(written, 0);
+        assert_eq!(skipped, vec!["router: the password has several lines", "modem: the password has several lines"]);
+        fs::remove_dir_all(&store).unwrap();
+    }
+}
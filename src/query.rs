@@ -0,0 +1,263 @@
+//! The search query language shared by `passman search` and the TUI.
+//!
+//! A query is a list of whitespace separated terms that must all match:
+//!
+//! - `git` fuzzy matches the name (with its folder), username, URL or tags
+//! - `tag:work`, `user:alice`, `url:github.com`, `name:`, `folder:` and
+//!   `notes:` only look at that field
+//! - `-tag:archived` excludes entries the term matches
+//! - `"client work"` matches the phrase as is, spaces included
+//! - `'exact` matches a substring, `^prefix` the start, `suffix$` the end and
+//!   `^whole$` the entire field (fzf's syntax)
+//!
+//! Terms are case-insensitive unless they contain an uppercase letter.
+
+/// A parsed query: entries must match every term.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    /// Set by a leading `-`: the entry must not match
+    pub negated: bool,
+    /// The field given as `field:`, or `None` for the default fields
+    pub field: Option<Field>,
+    pub kind: MatchKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Name,
+    User,
+    Url,
+    Tag,
+    Folder,
+    Notes,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "name" => Some(Self::Name),
+            "user" | "username" => Some(Self::User),
+            "url" => Some(Self::Url),
+            "tag" | "tags" => Some(Self::Tag),
+            "folder" => Some(Self::Folder),
+            "notes" | "note" => Some(Self::Notes),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchKind {
+    Fuzzy,
+    /// Substring, from `'text` or a quoted phrase
+    Exact,
+    Prefix,
+    Suffix,
+    /// The whole field, from `^text$`
+    Equal,
+}
+
+impl Query {
+    /// Parses a query. Every input is valid: anything that isn't an operator
+    /// is searched for literally, and terms left empty (such as a `tag:`
+    /// still being typed) are dropped so they match everything.
+    pub fn parse(input: &str) -> Self {
+        let mut terms = Vec::new();
+        let mut chars = input.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let (token, quoted) = read_token(&mut chars);
+            if let Some(term) = parse_term(&token, quoted) {
+                terms.push(term);
+            }
+        }
+        Query { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+/// Reads up to the next whitespace outside quotes. Returns the token with
+/// the quotes removed, and the byte offset where the quoted part started.
+fn read_token(chars: &mut std::iter::Peekable<std::str::Chars>) -> (String, Option<usize>) {
+    let mut token = String::new();
+    let mut quoted = None;
+    let mut in_quotes = false;
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() && !in_quotes {
+            break;
+        }
+        chars.next();
+        if c == '"' && (in_quotes || quoted.is_none()) {
+            in_quotes = !in_quotes;
+            quoted.get_or_insert(token.len());
+        } else {
+            token.push(c);
+        }
+    }
+    (token, quoted)
+}
+
+fn parse_term(token: &str, quoted: Option<usize>) -> Option<Term> {
+    // `quoted` is a byte offset into `token`, so the prefixes taken off
+    // `rest` are counted to compare against it
+    let mut rest = token;
+    let is_quoted_from = |rest: &str, offset: usize| quoted.is_some_and(|start| start <= token.len() - rest.len() + offset);
+
+    // A lone `-` is searched for, not an empty negation
+    let negated = rest.len() > 1 && rest.starts_with('-') && !is_quoted_from(rest, 0);
+    if negated {
+        rest = &rest[1..];
+    }
+
+    let mut field = None;
+    if let Some((name, after)) = rest.split_once(':') {
+        // Only before any quoted part, so `"a:b"` stays a phrase
+        if let Some(known) = Field::from_name(name).filter(|_| !is_quoted_from(rest, name.len())) {
+            field = Some(known);
+            rest = after;
+        }
+    }
+
+    let (kind, text) = if quoted.is_some() {
+        (MatchKind::Exact, rest)
+    } else if let Some(text) = rest.strip_prefix('\'') {
+        (MatchKind::Exact, text)
+    } else {
+        let prefix = rest.len() > 1 && rest.starts_with('^');
+        let suffix = rest.len() > 1 && rest.ends_with('$');
+        let text = &rest[usize::from(prefix)..rest.len() - usize::from(suffix)];
+        let kind = match (prefix, suffix) {
+            (true, true) => MatchKind::Equal,
+            (true, false) => MatchKind::Prefix,
+            (false, true) => MatchKind::Suffix,
+            (false, false) => MatchKind::Fuzzy,
+        };
+        (kind, text)
+    };
+
+    if text.is_empty() {
+        return None;
+    }
+    Some(Term {
+        negated,
+        field,
+        kind,
+        text: text.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(negated: bool, field: Option<Field>, kind: MatchKind, text: &str) -> Term {
+        Term {
+            negated,
+            field,
+            kind,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn splits_terms_on_whitespace() {
+        let query = Query::parse("  git   hub ");
+        assert_eq!(
+            query.terms,
+            vec![
+                term(false, None, MatchKind::Fuzzy, "git"),
+                term(false, None, MatchKind::Fuzzy, "hub"),
+            ]
+        );
+        assert!(Query::parse("   ").is_empty());
+    }
+
+    #[test]
+    fn parses_field_scopes() {
+        let query = Query::parse("tag:work user:alice url:github.com Notes:pin");
+        assert_eq!(
+            query.terms,
+            vec![
+                term(false, Some(Field::Tag), MatchKind::Fuzzy, "work"),
+                term(false, Some(Field::User), MatchKind::Fuzzy, "alice"),
+                term(false, Some(Field::Url), MatchKind::Fuzzy, "github.com"),
+                term(false, Some(Field::Notes), MatchKind::Fuzzy, "pin"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_searched_literally() {
+        let query = Query::parse("https://example.com url:https://example.com");
+        assert_eq!(
+            query.terms,
+            vec![
+                term(false, None, MatchKind::Fuzzy, "https://example.com"),
+                term(false, Some(Field::Url), MatchKind::Fuzzy, "https://example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_negation() {
+        let query = Query::parse("-tag:archived -old - -");
+        assert_eq!(
+            query.terms,
+            vec![
+                term(true, Some(Field::Tag), MatchKind::Fuzzy, "archived"),
+                term(true, None, MatchKind::Fuzzy, "old"),
+                term(false, None, MatchKind::Fuzzy, "-"),
+                term(false, None, MatchKind::Fuzzy, "-"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_anchors_and_exact_tokens() {
+        let query = Query::parse("^git hub$ ^bank$ 'exact ^ $");
+        assert_eq!(
+            query.terms,
+            vec![
+                term(false, None, MatchKind::Prefix, "git"),
+                term(false, None, MatchKind::Suffix, "hub"),
+                term(false, None, MatchKind::Equal, "bank"),
+                term(false, None, MatchKind::Exact, "exact"),
+                term(false, None, MatchKind::Fuzzy, "^"),
+                term(false, None, MatchKind::Fuzzy, "$"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_quoted_phrases() {
+        let query = Query::parse(r#""client work" tag:"side project" -"old stuff" "a:b" "unclosed phrase"#);
+        assert_eq!(
+            query.terms,
+            vec![
+                term(false, None, MatchKind::Exact, "client work"),
+                term(false, Some(Field::Tag), MatchKind::Exact, "side project"),
+                term(true, None, MatchKind::Exact, "old stuff"),
+                term(false, None, MatchKind::Exact, "a:b"),
+                term(false, None, MatchKind::Exact, "unclosed phrase"),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_empty_terms() {
+        assert!(Query::parse("tag: -user: ' \"\" ^$").is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::vault::PasswordEntry;
+
+/// A vault file that could be read (and decrypted) but not parsed.
+///
+/// While a vault is in this state `VaultManager` refuses to write to it, so
+/// the damaged file is never replaced by an empty or partial vault.
+#[derive(Debug, Clone)]
+pub struct CorruptVault {
+    pub message: String,
+    /// 1-based line and column of the parse error, when known
+    pub location: Option<(usize, usize)>,
+}
+
+impl CorruptVault {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            location: None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for CorruptVault {
+    fn from(error: serde_json::Error) -> Self {
+        // serde_json reports line 0 when the error has no position, e.g. for
+        // errors raised while converting from an in-memory `Value`
+        let location = (error.line() > 0).then(|| (error.line(), error.column()));
+        let message = match location {
+            // Strip the " at line X column Y" suffix, it is reported separately
+            Some(_) => error
+                .to_string()
+                .rsplit_once(" at line ")
+                .map(|(message, _)| message.to_string())
+                .unwrap_or_else(|| error.to_string()),
+            None => error.to_string(),
+        };
+        Self { message, location }
+    }
+}
+
+impl fmt::Display for CorruptVault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(
+                f,
+                "Vault file is corrupt at line {}, column {}: {}",
+                line, column, self.message
+            ),
+            None => write!(f, "Vault file is corrupt: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for CorruptVault {}
+
+/// Recovers every entry that still parses on its own from a damaged vault
+/// document.
+///
+/// The text is scanned for balanced `{ ... }` objects (ignoring braces inside
+/// strings) and each one is tried as a `PasswordEntry`, so a single broken
+/// entry or a truncated tail only loses the entries it actually touches.
+pub fn salvage_entries(text: &str) -> Vec<PasswordEntry> {
+    let mut entries: Vec<PasswordEntry> = Vec::new();
+    let mut open_braces = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' => open_braces.push(i),
+            '}' => {
+                if let Some(start) = open_braces.pop() {
+                    if let Ok(entry) = serde_json::from_str::<PasswordEntry>(&text[start..=i]) {
+                        // The same id can only show up twice if the damage
+                        // duplicated part of the file; keep the first copy
+                        if !entries.iter().any(|e| e.id == entry.id) {
+                            entries.push(entry);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    entries
+}
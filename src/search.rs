@@ -1,51 +1,176 @@
-use crate::vault::PasswordEntry;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::query::{Field, MatchKind, Query, Term};
+use crate::vault::{PasswordEntry, UsageEvent, UsageKind};
+
+// Scoring follows fzf's: every matched character is worth SCORE_MATCH, gaps
+// between matches cost a little, and characters at the start of a word,
+// path component or camelCase hump earn a bonus, as do runs of consecutive
+// matches.
+const SCORE_MATCH: i32 = 16;
+const SCORE_GAP_START: i32 = -3;
+const SCORE_GAP_EXTENSION: i32 = -1;
+const BONUS_BOUNDARY_WHITE: i32 = 10;
+const BONUS_BOUNDARY_DELIMITER: i32 = 9;
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_NON_WORD: i32 = 8;
+const BONUS_CAMEL_CASE: i32 = 7;
+const BONUS_CONSECUTIVE: i32 = 4;
+/// The first pattern character's bonus counts this many times over
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+
+/// Separators of paths and lists; a match right after one is as good as at
+/// the start of a word.
+const DELIMITERS: [char; 5] = ['/', ',', ':', ';', '|'];
+
+/// Points a copy earns by age, in days, like Firefox's frecency buckets. A
+/// view earns half. With `MAX_USAGE_EVENTS` kept per entry, a heavily used
+/// entry gains about as much as a good match of a short term.
+const FRECENCY_BUCKETS: [(i64, i32); 4] = [(4, 10), (14, 7), (31, 5), (90, 3)];
+/// Points of a copy older than every bucket
+const FRECENCY_OLD: i32 = 1;
+
+/// How `SearchEngine::search` orders the entries that match, set with
+/// `sort` in `config.toml`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Match score plus how often and how lately the entry was used
+    #[default]
+    Frecency,
+    /// Most recently used first, then by match score
+    Recent,
+    /// By folder and name
+    Alphabetical,
+    /// Match score only
+    Match,
+}
 
 pub struct SearchEngine {
-    // Simple search implementation without external dependencies
+    sort: SortOrder,
 }
 
 impl SearchEngine {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(sort: SortOrder) -> Self {
+        Self { sort }
     }
 
-    pub fn search(&self, entries: &[PasswordEntry], query: &str) -> Vec<SearchResult> {
-        let mut results = Vec::new();
-
-        for entry in entries {
-            let search_text = entry.name.clone();
-
-            if self.matches(&search_text, query) {
-                let score = self.calculate_score(&search_text, query);
-                results.push(SearchResult {
+    /// Entries matching `query` (see `query` for the syntax), in the engine's
+    /// sort order. `usage` is the vault's usage history by entry id.
+    ///
+    /// Sorting is stable, so entries that tie keep their vault order. With
+    /// a query, shorter names win ties as there is less left unmatched.
+    pub fn search(
+        &self,
+        entries: &[PasswordEntry],
+        usage: &BTreeMap<String, Vec<UsageEvent>>,
+        query: &str,
+    ) -> Vec<SearchResult> {
+        let query = Query::parse(query);
+        let mut results: Vec<SearchResult> = entries
+            .iter()
+            .filter_map(|entry| {
+                let (score, highlights) = self.match_entry(entry, &query)?;
+                Some(SearchResult {
                     entry: entry.clone(),
                     score,
-                });
+                    highlights,
+                })
+            })
+            .collect();
+
+        let events = |entry: &PasswordEntry| usage.get(&entry.id).map_or(&[][..], Vec::as_slice);
+        let tie_break = |r: &SearchResult| if query.is_empty() { 0 } else { entry_path(&r.entry).chars().count() };
+        match self.sort {
+            SortOrder::Frecency => {
+                let now = Utc::now();
+                for result in &mut results {
+                    result.score += frecency(events(&result.entry), now);
+                }
+                results.sort_by_key(|r| (Reverse(r.score), tie_break(r)));
+            }
+            SortOrder::Recent => {
+                results.sort_by_key(|r| (Reverse(last_used(events(&r.entry))), Reverse(r.score), tie_break(r)));
             }
+            SortOrder::Alphabetical => results.sort_by_cached_key(|r| entry_path(&r.entry).to_lowercase()),
+            SortOrder::Match => results.sort_by_key(|r| (Reverse(r.score), tie_break(r))),
         }
-
-        // Sort by score (higher is better)
-        results.sort_by(|a, b| b.score.cmp(&a.score));
         results
     }
 
-    fn matches(&self, text: &str, query: &str) -> bool {
-        let text_lower = text.to_lowercase();
-        let query_lower = query.to_lowercase();
-        text_lower.contains(&query_lower)
+    /// Scores an entry against every term, or `None` if any term rules it out.
+    fn match_entry(&self, entry: &PasswordEntry, query: &Query) -> Option<(i32, Highlights)> {
+        let mut total = 0;
+        let mut highlights = Highlights::default();
+        for term in &query.terms {
+            match (self.match_term(entry, term), term.negated) {
+                (Some(_), true) | (None, false) => return None,
+                (None, true) => {}
+                (Some((score, matched)), false) => {
+                    total += score;
+                    highlights.merge(matched);
+                }
+            }
+        }
+        Some((total, highlights))
     }
 
-    fn calculate_score(&self, text: &str, query: &str) -> i32 {
-        let text_lower = text.to_lowercase();
-        let query_lower = query.to_lowercase();
-        
-        if text_lower.starts_with(&query_lower) {
-            100
-        } else if text_lower.contains(&query_lower) {
-            50
-        } else {
-            0
+    /// The best scoring field the term matches, preferring earlier fields
+    /// when scores tie.
+    fn match_term(&self, entry: &PasswordEntry, term: &Term) -> Option<(i32, Highlights)> {
+        let matches = |text: &str| match_text(text, term.kind, &term.text);
+        let folder_len = entry.folder.as_ref().map_or(0, |folder| folder.chars().count() + 1);
+        let mut candidates: Vec<(i32, Highlights)> = Vec::new();
+
+        let default = term.field.is_none();
+        if default {
+            if let Some(m) = matches(&entry_path(entry)) {
+                candidates.push((m.score, Highlights { path: m.indices, ..Highlights::default() }));
+            }
         }
+        if term.field == Some(Field::Name) {
+            if let Some(m) = matches(&entry.name) {
+                let path = m.indices.iter().map(|i| i + folder_len).collect();
+                candidates.push((m.score, Highlights { path, ..Highlights::default() }));
+            }
+        }
+        if term.field == Some(Field::Folder) {
+            if let Some(m) = entry.folder.as_deref().and_then(matches) {
+                candidates.push((m.score, Highlights { path: m.indices, ..Highlights::default() }));
+            }
+        }
+        if default || term.field == Some(Field::User) {
+            if let Some(m) = entry.username.as_deref().and_then(matches) {
+                candidates.push((m.score, Highlights { username: m.indices, ..Highlights::default() }));
+            }
+        }
+        if default || term.field == Some(Field::Url) {
+            if let Some(m) = entry.url.as_deref().and_then(matches) {
+                candidates.push((m.score, Highlights { url: m.indices, ..Highlights::default() }));
+            }
+        }
+        if default || term.field == Some(Field::Tag) {
+            for (i, tag) in entry.tags.iter().enumerate() {
+                if let Some(m) = matches(tag) {
+                    let mut tags = vec![Vec::new(); entry.tags.len()];
+                    tags[i] = m.indices;
+                    candidates.push((m.score, Highlights { tags, ..Highlights::default() }));
+                }
+            }
+        }
+        // Notes aren't listed, so there is nothing to highlight
+        if term.field == Some(Field::Notes) {
+            if let Some(m) = entry.notes.as_deref().and_then(matches) {
+                candidates.push((m.score, Highlights::default()));
+            }
+        }
+
+        candidates
+            .into_iter()
+            .reduce(|best, candidate| if candidate.0 > best.0 { candidate } else { best })
     }
 }
 
@@ -53,4 +178,388 @@ impl SearchEngine {
 pub struct SearchResult {
     pub entry: PasswordEntry,
     pub score: i32,
+    #[allow(dead_code)] // The CLI lists results without highlighting
+    pub highlights: Highlights,
+}
+
+/// Character indices of the matched characters in each displayed field.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Highlights {
+    /// Into `entry_path`, i.e. `folder/name`
+    pub path: Vec<usize>,
+    pub username: Vec<usize>,
+    pub url: Vec<usize>,
+    /// One list per tag, or empty if no tag matched
+    pub tags: Vec<Vec<usize>>,
+}
+
+impl Highlights {
+    fn merge(&mut self, other: Highlights) {
+        self.path.extend(other.path);
+        self.username.extend(other.username);
+        self.url.extend(other.url);
+        if self.tags.len() < other.tags.len() {
+            self.tags.resize(other.tags.len(), Vec::new());
+        }
+        for (tag, indices) in self.tags.iter_mut().zip(other.tags) {
+            tag.extend(indices);
+        }
+    }
+}
+
+/// Scores how often and how lately an entry was used, as of `now`.
+pub fn frecency(events: &[UsageEvent], now: DateTime<Utc>) -> i32 {
+    events
+        .iter()
+        .map(|event| {
+            let age = (now - event.at).num_days();
+            let points = FRECENCY_BUCKETS
+                .iter()
+                .find(|(days, _)| age <= *days)
+                .map_or(FRECENCY_OLD, |(_, points)| *points);
+            match event.kind {
+                UsageKind::Copy => points,
+                UsageKind::View => points / 2,
+            }
+        })
+        .sum()
+}
+
+fn last_used(events: &[UsageEvent]) -> Option<DateTime<Utc>> {
+    events.iter().map(|event| event.at).max()
+}
+
+/// `folder/name`, the way entries are listed.
+pub fn entry_path(entry: &PasswordEntry) -> String {
+    match &entry.folder {
+        Some(folder) => format!("{}/{}", folder, entry.name),
+        None => entry.name.clone(),
+    }
+}
+
+/// Matches one term's text against a field the way its `kind` asks for.
+fn match_text(text: &str, kind: MatchKind, pattern: &str) -> Option<FuzzyMatch> {
+    match kind {
+        MatchKind::Fuzzy => fuzzy_match(text, pattern),
+        _ => exact_match(text, kind, pattern),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    pub score: i32,
+    /// Character (not byte) indices into the text, in ascending order
+    pub indices: Vec<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+    White,
+    Delimiter,
+    NonWord,
+    Lower,
+    Upper,
+    Digit,
+    /// Letters without case, e.g. CJK
+    Letter,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::White
+    } else if DELIMITERS.contains(&c) {
+        CharClass::Delimiter
+    } else if c.is_lowercase() {
+        CharClass::Lower
+    } else if c.is_uppercase() {
+        CharClass::Upper
+    } else if c.is_numeric() {
+        CharClass::Digit
+    } else if c.is_alphabetic() {
+        CharClass::Letter
+    } else {
+        CharClass::NonWord
+    }
+}
+
+/// Bonus for matching a character of class `current` that follows one of
+/// class `previous`.
+fn bonus_for(previous: CharClass, current: CharClass) -> i32 {
+    use CharClass::*;
+    match (previous, current) {
+        (_, White) => BONUS_BOUNDARY_WHITE,
+        (_, Delimiter | NonWord) => BONUS_NON_WORD,
+        (White, _) => BONUS_BOUNDARY_WHITE,
+        (Delimiter, _) => BONUS_BOUNDARY_DELIMITER,
+        (NonWord, _) => BONUS_BOUNDARY,
+        (Lower, Upper) => BONUS_CAMEL_CASE,
+        (Lower | Upper | Letter, Digit) => BONUS_CAMEL_CASE,
+        _ => 0,
+    }
+}
+
+fn fold_case(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Finds `pattern` as a subsequence of `text` and returns the best scoring
+/// alignment, like fzf and skim do. Matching ignores case unless the pattern
+/// contains an uppercase letter.
+pub fn fuzzy_match(text: &str, pattern: &str) -> Option<FuzzyMatch> {
+    let case_sensitive = pattern.chars().any(char::is_uppercase);
+    let fold = |c: char| if case_sensitive { c } else { fold_case(c) };
+    let pattern: Vec<char> = pattern.chars().map(fold).collect();
+    if pattern.is_empty() {
+        return Some(FuzzyMatch { score: 0, indices: Vec::new() });
+    }
+    let text: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = text.iter().copied().map(fold).collect();
+
+    // Cheap rejection before the quadratic part
+    let mut remaining = folded.iter();
+    if !pattern.iter().all(|p| remaining.any(|c| c == p)) {
+        return None;
+    }
+
+    let (n, m) = (text.len(), pattern.len());
+    let bonuses: Vec<i32> = (0..n)
+        .map(|j| {
+            let previous = if j == 0 { CharClass::White } else { char_class(text[j - 1]) };
+            bonus_for(previous, char_class(text[j]))
+        })
+        .collect();
+
+    // score[i][j]: best score with pattern[i] matched at text[j];
+    // run_bonus[i][j]: bonus of the first match in the consecutive run ending
+    // there; from[i][j]: where pattern[i - 1] was matched, for backtracking
+    let mut score = vec![vec![None; n]; m];
+    let mut run_bonus = vec![vec![0; n]; m];
+    let mut from = vec![vec![0; n]; m];
+
+    for j in 0..n {
+        if folded[j] == pattern[0] {
+            score[0][j] = Some(SCORE_MATCH + bonuses[j] * BONUS_FIRST_CHAR_MULTIPLIER);
+            run_bonus[0][j] = bonuses[j];
+        }
+    }
+    for i in 1..m {
+        // Best way to reach column j after a gap of at least one character
+        let mut gap: Option<(i32, usize)> = None;
+        for j in i..n {
+            if j >= 2 {
+                let extended = gap.map(|(score, k)| (score + SCORE_GAP_EXTENSION, k));
+                let started = score[i - 1][j - 2].map(|score| (score + SCORE_GAP_START, j - 2));
+                gap = match (extended, started) {
+                    (Some(extended), Some(started)) if extended.0 > started.0 => Some(extended),
+                    (extended, started) => started.or(extended),
+                };
+            }
+            if folded[j] != pattern[i] {
+                continue;
+            }
+
+            if let Some(previous) = score[i - 1][j - 1] {
+                let mut chunk = run_bonus[i - 1][j - 1];
+                // A new word inside the run counts from there instead
+                if bonuses[j] >= BONUS_BOUNDARY && bonuses[j] > chunk {
+                    chunk = bonuses[j];
+                }
+                score[i][j] = Some(previous + SCORE_MATCH + chunk.max(bonuses[j]).max(BONUS_CONSECUTIVE));
+                run_bonus[i][j] = chunk;
+                from[i][j] = j - 1;
+            }
+            if let Some((gapped, k)) = gap {
+                let candidate = gapped + SCORE_MATCH + bonuses[j];
+                if score[i][j].is_none_or(|current| candidate > current) {
+                    score[i][j] = Some(candidate);
+                    run_bonus[i][j] = bonuses[j];
+                    from[i][j] = k;
+                }
+            }
+        }
+    }
+
+    let (mut j, best) = score[m - 1]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, score)| score.map(|score| (j, score)))
+        .fold(None, |best: Option<(usize, i32)>, (j, score)| match best {
+            Some((_, best_score)) if best_score >= score => best,
+            _ => Some((j, score)),
+        })?;
+
+    let mut indices = vec![0; m];
+    for i in (0..m).rev() {
+        indices[i] = j;
+        j = from[i][j];
+    }
+    Some(FuzzyMatch { score: best, indices })
+}
+
+/// Finds `pattern` as a contiguous substring, at the start of `text` for
+/// `Prefix`, at the end for `Suffix` or as all of it for `Equal`. Scored
+/// like a fuzzy match without gaps, so the two rank together sensibly.
+fn exact_match(text: &str, kind: MatchKind, pattern: &str) -> Option<FuzzyMatch> {
+    let case_sensitive = pattern.chars().any(char::is_uppercase);
+    let fold = |c: char| if case_sensitive { c } else { fold_case(c) };
+    let pattern: Vec<char> = pattern.chars().map(fold).collect();
+    let text: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = text.iter().copied().map(fold).collect();
+    let (n, m) = (text.len(), pattern.len());
+    if m == 0 || m > n {
+        return (m == 0).then(|| FuzzyMatch { score: 0, indices: Vec::new() });
+    }
+
+    let starts: Vec<usize> = match kind {
+        MatchKind::Prefix => vec![0],
+        MatchKind::Suffix => vec![n - m],
+        MatchKind::Equal if m == n => vec![0],
+        MatchKind::Equal => Vec::new(),
+        MatchKind::Exact | MatchKind::Fuzzy => (0..=n - m).collect(),
+    };
+    starts
+        .into_iter()
+        .filter(|&start| folded[start..start + m] == pattern[..])
+        .map(|start| {
+            let previous = if start == 0 { CharClass::White } else { char_class(text[start - 1]) };
+            let bonus = bonus_for(previous, char_class(text[start]));
+            let score = SCORE_MATCH * m as i32
+                + bonus * BONUS_FIRST_CHAR_MULTIPLIER
+                + bonus.max(BONUS_CONSECUTIVE) * (m as i32 - 1);
+            FuzzyMatch { score, indices: (start..start + m).collect() }
+        })
+        .fold(None, |best: Option<FuzzyMatch>, candidate| match best {
+            Some(best) if best.score >= candidate.score => Some(best),
+            _ => Some(candidate),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_subsequences_only() {
+        assert!(fuzzy_match("github", "ghb").is_some());
+        assert!(fuzzy_match("github", "bgh").is_none());
+        assert_eq!(fuzzy_match("github", "").unwrap().indices, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn prefers_word_boundaries_and_camel_case() {
+        // "gl" should take the G of Gitlab, not the g in "signal"
+        assert_eq!(fuzzy_match("signal Gitlab", "gl").unwrap().indices, vec![7, 10]);
+        assert_eq!(fuzzy_match("myGitHub", "gh").unwrap().indices, vec![2, 5]);
+        assert_eq!(fuzzy_match("Work/Servers/db", "ws").unwrap().indices, vec![0, 5]);
+    }
+
+    #[test]
+    fn consecutive_runs_beat_scattered_matches() {
+        let run = fuzzy_match("xgitx", "git").unwrap();
+        let scattered = fuzzy_match("xgxixt", "git").unwrap();
+        assert!(run.score > scattered.score);
+    }
+
+    #[test]
+    fn uppercase_in_the_pattern_makes_it_case_sensitive() {
+        assert!(fuzzy_match("GitHub", "github").is_some());
+        assert!(fuzzy_match("github", "GitHub").is_none());
+    }
+
+    #[test]
+    fn indices_count_characters_not_bytes() {
+        assert_eq!(fuzzy_match("café bar", "fb").unwrap().indices, vec![2, 5]);
+    }
+
+    fn entry(name: &str, folder: Option<&str>, username: &str, tags: &[&str]) -> PasswordEntry {
+        let mut entry = PasswordEntry::new(name, "secret");
+        entry.folder = folder.map(str::to_string);
+        entry.username = Some(username.to_string());
+        entry.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        entry
+    }
+
+    fn names(entries: &[PasswordEntry], query: &str) -> Vec<String> {
+        SearchEngine::new(SortOrder::Match)
+            .search(entries, &BTreeMap::new(), query)
+            .into_iter()
+            .map(|result| result.entry.name)
+            .collect()
+    }
+
+    #[test]
+    fn evaluates_scoped_and_negated_terms() {
+        let entries = vec![
+            entry("github", None, "alice", &["work"]),
+            entry("gitlab", None, "bob", &["work", "archived"]),
+            entry("gitea", None, "alice", &["home"]),
+        ];
+        assert_eq!(names(&entries, "tag:work -tag:archived"), vec!["github"]);
+        assert_eq!(names(&entries, "user:alice git"), vec!["gitea", "github"]);
+        assert_eq!(names(&entries, "-user:alice"), vec!["gitlab"]);
+        assert!(names(&entries, "user:work").is_empty());
+        assert_eq!(names(&entries, ""), vec!["github", "gitlab", "gitea"]);
+    }
+
+    #[test]
+    fn anchors_and_exact_terms_match_contiguously() {
+        let entries = vec![
+            entry("github", None, "alice", &[]),
+            entry("my-git-hub", None, "alice", &[]),
+            entry("hub", None, "alice", &[]),
+        ];
+        assert_eq!(names(&entries, "'thub"), vec!["github"]);
+        assert_eq!(names(&entries, "^git"), vec!["github"]);
+        // "hub" starting a word beats it sitting inside one
+        assert_eq!(names(&entries, "hub$"), vec!["hub", "my-git-hub", "github"]);
+        assert_eq!(names(&entries, "^hub$"), vec!["hub"]);
+    }
+
+    fn used(kind: UsageKind, days_ago: i64) -> UsageEvent {
+        UsageEvent {
+            kind,
+            at: Utc::now() - chrono::Duration::days(days_ago),
+        }
+    }
+
+    #[test]
+    fn frecency_favours_recent_copies() {
+        let now = Utc::now();
+        assert_eq!(frecency(&[], now), 0);
+        assert_eq!(frecency(&[used(UsageKind::Copy, 0)], now), 10);
+        assert_eq!(frecency(&[used(UsageKind::View, 0)], now), 5);
+        assert_eq!(frecency(&[used(UsageKind::Copy, 20), used(UsageKind::Copy, 365)], now), 5 + 1);
+    }
+
+    #[test]
+    fn sort_orders() {
+        let entries = vec![
+            entry("github", None, "alice", &[]),
+            entry("gitlab", None, "alice", &[]),
+            entry("bitbucket", None, "alice", &[]),
+        ];
+        let mut usage = BTreeMap::new();
+        usage.insert(entries[1].id.clone(), vec![used(UsageKind::Copy, 10); 3]);
+        usage.insert(entries[2].id.clone(), vec![used(UsageKind::View, 0)]);
+
+        let sorted = |sort| -> Vec<String> {
+            SearchEngine::new(sort)
+                .search(&entries, &usage, "")
+                .into_iter()
+                .map(|result| result.entry.name)
+                .collect()
+        };
+        assert_eq!(sorted(SortOrder::Frecency), vec!["gitlab", "bitbucket", "github"]);
+        assert_eq!(sorted(SortOrder::Recent), vec!["bitbucket", "gitlab", "github"]);
+        assert_eq!(sorted(SortOrder::Alphabetical), vec!["bitbucket", "github", "gitlab"]);
+        assert_eq!(sorted(SortOrder::Match), vec!["github", "gitlab", "bitbucket"]);
+    }
+
+    #[test]
+    fn name_highlights_are_offset_past_the_folder() {
+        let entries = vec![entry("db", Some("Work"), "admin", &["prod", "sql"])];
+        let results = SearchEngine::new(SortOrder::Match).search(&entries, &BTreeMap::new(), "name:db tag:sql");
+        assert_eq!(results[0].highlights.path, vec![5, 6]);
+        assert_eq!(results[0].highlights.tags, vec![vec![], vec![0, 1, 2]]);
+    }
 }
@@ -0,0 +1,681 @@
+use chrono::Datelike;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const COMMON_PASSWORDS: &str = include_str!("wordlists/common_passwords.txt");
+const ENGLISH_WORDS: &str = include_str!("wordlists/bip39_english.txt");
+
+/// Guess rate of an offline attack on a slow password hash, the scenario
+/// crack times are reported for
+const GUESSES_PER_SECOND: f64 = 1e4;
+
+/// Cost of each character not covered by any pattern
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_SINGLE_CHAR_GUESSES: f64 = 10.0;
+const MIN_MULTI_CHAR_GUESSES: f64 = 50.0;
+
+/// Penalty for every pattern beyond the first, so long passwords made of
+/// many tiny matches aren't rated as weaker than pure brute force
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 1e4;
+
+/// Years close to the present are guessed first
+const MIN_YEAR_SPACE: f64 = 20.0;
+
+/// Longer passwords are only analysed up to this many characters; anything
+/// beyond adds brute-force guesses
+const MAX_ANALYSED_LENGTH: usize = 100;
+
+const KEYBOARD_ROWS: [(&str, &str, f64); 4] = [
+    ("`1234567890-=", "~!@#$%^&*()_+", 0.0),
+    ("qwertyuiop[]\\", "QWERTYUIOP{}|", 0.5),
+    ("asdfghjkl;'", "ASDFGHJKL:\"", 0.75),
+    ("zxcvbnm,./", "ZXCVBNM<>?", 1.25),
+];
+/// Number of keys a keyboard walk can start on, and the average number of
+/// neighbours of each key
+const KEYBOARD_STARTING_POSITIONS: f64 = 94.0;
+const KEYBOARD_AVERAGE_DEGREE: f64 = 4.6;
+
+const L33T_SUBSTITUTIONS: [(char, char); 12] = [
+    ('4', 'a'),
+    ('@', 'a'),
+    ('8', 'b'),
+    ('3', 'e'),
+    ('6', 'g'),
+    ('!', 'i'),
+    ('0', 'o'),
+    ('$', 's'),
+    ('5', 's'),
+    ('7', 't'),
+    ('+', 't'),
+    ('2', 'z'),
+];
+
+/// How hard a password is to guess, estimated zxcvbn-style: the password is
+/// split into the cheapest sequence of known patterns (dictionary words,
+/// keyboard walks, sequences, repeats and dates) and brute-forced gaps.
+#[derive(Debug, Clone)]
+pub struct Strength {
+    /// 0 (too guessable) to 4 (very unguessable)
+    pub score: u8,
+    pub guesses: f64,
+    pub crack_time_seconds: f64,
+    /// What makes the password weak, if anything in particular does
+    pub warning: Option<&'static str>,
+}
+
+impl Strength {
+    pub fn label(&self) -> &'static str {
+        match self.score {
+            0 => "very weak",
+            1 => "weak",
+            2 => "fair",
+            3 => "strong",
+            _ => "very strong",
+        }
+    }
+
+    /// A human readable estimate such as "3 hours" or "centuries".
+    pub fn crack_time(&self) -> String {
+        display_time(self.crack_time_seconds)
+    }
+
+    /// One-line summary, e.g. "2/4 (fair), ~1e7 guesses, 17 minutes to crack".
+    pub fn summary(&self) -> String {
+        format!(
+            "{}/4 ({}), ~1e{:.0} guesses, {} to crack",
+            self.score,
+            self.label(),
+            self.guesses.log10().floor(),
+            self.crack_time()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pattern {
+    CommonPassword,
+    EnglishWord,
+    UserInput,
+    Spatial { turns: usize },
+    Sequence,
+    Repeat { unit_length: usize },
+    Date { year_only: bool },
+}
+
+struct Match {
+    start: usize,
+    /// Exclusive
+    end: usize,
+    guesses: f64,
+    pattern: Pattern,
+}
+
+/// Estimates the strength of `password`. `user_inputs` are strings an
+/// attacker would try early, such as the entry's name, username and URL.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let analysed = &chars[..chars.len().min(MAX_ANALYSED_LENGTH)];
+    let overflow = (chars.len() - analysed.len()) as f64;
+
+    let (log_guesses, matches) = most_guessable(analysed, &user_dictionary(user_inputs));
+    let log_guesses = log_guesses + overflow * BRUTEFORCE_CARDINALITY.log10();
+    let guesses = 10f64.powf(log_guesses);
+    let score = score_for(guesses);
+
+    Strength {
+        score,
+        guesses,
+        crack_time_seconds: guesses / GUESSES_PER_SECOND,
+        warning: (score <= 2).then(|| {
+            warning_for(&matches, analysed.len()).unwrap_or("Add more characters or words to make it longer")
+        }),
+    }
+}
+
+fn score_for(guesses: f64) -> u8 {
+    // The small delta keeps exact powers of ten on the weaker side
+    const DELTA: f64 = 5.0;
+    match guesses {
+        g if g < 1e3 + DELTA => 0,
+        g if g < 1e6 + DELTA => 1,
+        g if g < 1e8 + DELTA => 2,
+        g if g < 1e10 + DELTA => 3,
+        _ => 4,
+    }
+}
+
+/// Finds the sequence of patterns covering `chars` that needs the fewest
+/// guesses. Returns log10 of the guesses and the patterns used.
+fn most_guessable(chars: &[char], user_words: &HashMap<String, usize>) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    if n == 0 {
+        return (0.0, Vec::new());
+    }
+
+    let mut matches = Vec::new();
+    dictionary_matches(chars, user_words, &mut matches);
+    spatial_matches(chars, &mut matches);
+    sequence_matches(chars, &mut matches);
+    repeat_matches(chars, user_words, &mut matches);
+    date_matches(chars, &mut matches);
+
+    let mut by_end: Vec<Vec<usize>> = vec![Vec::new(); n + 1];
+    for (i, m) in matches.iter().enumerate() {
+        by_end[m.end].push(i);
+    }
+
+    // best[k][j]: log10 of the product of guesses covering the first j
+    // characters with exactly k patterns, and how that was reached
+    let mut best = vec![vec![(f64::INFINITY, 0usize, None::<usize>); n + 1]; n + 1];
+    best[0][0] = (0.0, 0, None);
+    for j in 1..=n {
+        for k in 1..=j {
+            let mut cell = best[k][j];
+            for &index in &by_end[j] {
+                let m = &matches[index];
+                let previous = best[k - 1][m.start].0;
+                let candidate = previous + m.guesses.log10();
+                if candidate < cell.0 {
+                    cell = (candidate, m.start, Some(index));
+                }
+            }
+            for (start, reached) in best[k - 1][..j].iter().enumerate() {
+                let candidate = reached.0 + bruteforce_guesses(j - start).log10();
+                if candidate < cell.0 {
+                    cell = (candidate, start, None);
+                }
+            }
+            best[k][j] = cell;
+        }
+    }
+
+    let mut log_factorial = 0.0;
+    let mut optimum = (f64::INFINITY, 0);
+    for (k, row) in best.iter().enumerate().skip(1) {
+        log_factorial += (k as f64).log10();
+        let product = row[n].0 + log_factorial;
+        let sequence_floor = (k - 1) as f64 * MIN_GUESSES_BEFORE_GROWING_SEQUENCE.log10();
+        let total = log10_sum(product, sequence_floor);
+        if total < optimum.0 {
+            optimum = (total, k);
+        }
+    }
+
+    let mut used = Vec::new();
+    let (mut k, mut j) = (optimum.1, n);
+    while k > 0 {
+        let (_, start, index) = best[k][j];
+        if let Some(index) = index {
+            used.push(index);
+        }
+        j = start;
+        k -= 1;
+    }
+    let mut chosen: Vec<Match> = Vec::new();
+    for (i, m) in matches.into_iter().enumerate() {
+        if used.contains(&i) {
+            chosen.push(m);
+        }
+    }
+    (optimum.0, chosen)
+}
+
+fn bruteforce_guesses(length: usize) -> f64 {
+    let minimum = if length == 1 { MIN_SINGLE_CHAR_GUESSES } else { MIN_MULTI_CHAR_GUESSES };
+    BRUTEFORCE_CARDINALITY.powi(length as i32).max(minimum + 1.0)
+}
+
+/// log10(10^a + 10^b) without overflowing.
+fn log10_sum(a: f64, b: f64) -> f64 {
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    high + (1.0 + 10f64.powf(low - high)).log10()
+}
+
+fn push_match(matches: &mut Vec<Match>, start: usize, end: usize, guesses: f64, pattern: Pattern) {
+    let minimum = if end - start == 1 { MIN_SINGLE_CHAR_GUESSES } else { MIN_MULTI_CHAR_GUESSES };
+    matches.push(Match { start, end, guesses: guesses.max(minimum), pattern });
+}
+
+/// Built-in dictionaries, word to (rank, pattern). Lower ranks are guessed
+/// earlier.
+fn ranked_dictionary() -> &'static HashMap<String, (usize, Pattern)> {
+    static DICTIONARY: OnceLock<HashMap<String, (usize, Pattern)>> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        let english: Vec<&str> = ENGLISH_WORDS.lines().map(str::trim).filter(|w| !w.is_empty()).collect();
+        // The word list isn't ordered by frequency, so every word is ranked
+        // as if drawn uniformly from it
+        let mut dictionary: HashMap<String, (usize, Pattern)> = english
+            .iter()
+            .map(|word| (word.to_string(), (english.len(), Pattern::EnglishWord)))
+            .collect();
+        for (rank, word) in COMMON_PASSWORDS.lines().map(str::trim).filter(|w| !w.is_empty()).enumerate() {
+            dictionary.insert(word.to_string(), (rank + 1, Pattern::CommonPassword));
+        }
+        dictionary
+    })
+}
+
+fn user_dictionary(user_inputs: &[&str]) -> HashMap<String, usize> {
+    let mut words = HashMap::new();
+    let tokens = user_inputs.iter().flat_map(|input| {
+        std::iter::once(input.to_lowercase()).chain(
+            input
+                .split(|c: char| !c.is_alphanumeric())
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>(),
+        )
+    });
+    for token in tokens.filter(|t| t.chars().count() >= 3) {
+        let rank = words.len() + 1;
+        words.entry(token).or_insert(rank);
+    }
+    words
+}
+
+fn dictionary_matches(chars: &[char], user_words: &HashMap<String, usize>, matches: &mut Vec<Match>) {
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    if lower.len() != chars.len() {
+        // Case folding changed the length; positions would no longer line up
+        return;
+    }
+
+    let mut candidates: Vec<(Vec<char>, bool)> = vec![(lower.clone(), false)];
+    // "1" and "|" could stand for an i or an l; both are common
+    for one in ['i', 'l'] {
+        let unleeted = unleet(&lower, one);
+        if !candidates.iter().any(|(text, _)| *text == unleeted) {
+            candidates.push((unleeted, true));
+        }
+    }
+    let reversed: Vec<char> = lower.iter().rev().copied().collect();
+
+    for (text, l33t) in &candidates {
+        for (start, end, rank, pattern) in lookup_words(text, user_words) {
+            let substitutions = (start..end).filter(|&i| text[i] != lower[i]).count();
+            if *l33t && substitutions == 0 {
+                continue;
+            }
+            let guesses = rank as f64
+                * uppercase_variations(&chars[start..end])
+                * 2f64.powi(substitutions as i32);
+            push_match(matches, start, end, guesses, pattern);
+        }
+    }
+
+    let n = chars.len();
+    for (start, end, rank, pattern) in lookup_words(&reversed, user_words) {
+        let (start, end) = (n - end, n - start);
+        if end - start < 4 {
+            continue;
+        }
+        let guesses = rank as f64 * uppercase_variations(&chars[start..end]) * 2.0;
+        push_match(matches, start, end, guesses, pattern);
+    }
+}
+
+/// Every dictionary word found in `text` as (start, end, rank, pattern).
+fn lookup_words(text: &[char], user_words: &HashMap<String, usize>) -> Vec<(usize, usize, usize, Pattern)> {
+    let dictionary = ranked_dictionary();
+    let mut found = Vec::new();
+    for start in 0..text.len() {
+        let mut word = String::new();
+        for end in start + 1..=text.len() {
+            word.push(text[end - 1]);
+            if let Some(&rank) = user_words.get(&word) {
+                found.push((start, end, rank, Pattern::UserInput));
+            }
+            if let Some(&(rank, pattern)) = dictionary.get(&word) {
+                if end - start >= 3 || pattern == Pattern::CommonPassword {
+                    found.push((start, end, rank, pattern));
+                }
+            }
+        }
+    }
+    found
+}
+
+fn unleet(chars: &[char], one: char) -> Vec<char> {
+    chars
+        .iter()
+        .map(|&c| match c {
+            '1' | '|' => one,
+            _ => L33T_SUBSTITUTIONS
+                .iter()
+                .find(|(leet, _)| *leet == c)
+                .map_or(c, |(_, plain)| *plain),
+        })
+        .collect()
+}
+
+/// Extra guesses needed for the capitalisation of a dictionary word.
+fn uppercase_variations(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let lower = word.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+    let first_only = word[0].is_uppercase() && upper == 1;
+    let last_only = word[word.len() - 1].is_uppercase() && upper == 1;
+    if lower == 0 || first_only || last_only {
+        return 2.0;
+    }
+    (1..=upper.min(lower)).map(|i| binomial(upper + lower, i)).sum()
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (1..=k).fold(1.0, |acc, i| acc * (n + 1 - i) as f64 / i as f64)
+}
+
+/// Position of a key on a US keyboard and whether Shift is needed.
+fn key_position(c: char) -> Option<(f64, f64, bool)> {
+    KEYBOARD_ROWS.iter().enumerate().find_map(|(row, (plain, shifted, offset))| {
+        plain
+            .chars()
+            .position(|k| k == c)
+            .map(|col| (row as f64, col as f64 + offset, false))
+            .or_else(|| {
+                shifted
+                    .chars()
+                    .position(|k| k == c)
+                    .map(|col| (row as f64, col as f64 + offset, true))
+            })
+    })
+}
+
+fn spatial_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let positions: Vec<Option<(f64, f64, bool)>> = chars.iter().map(|&c| key_position(c)).collect();
+    let adjacent = |a: (f64, f64, bool), b: (f64, f64, bool)| {
+        let (rows, cols) = (b.0 - a.0, b.1 - a.1);
+        (rows == 0.0 && cols.abs() == 1.0) || (rows.abs() == 1.0 && cols.abs() < 1.0)
+    };
+
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut direction = None;
+        while end < chars.len() {
+            let (Some(a), Some(b)) = (positions[end - 1], positions[end]) else { break };
+            if !adjacent(a, b) {
+                break;
+            }
+            let step = ((b.0 - a.0) as i32, ((b.1 - a.1) * 4.0).round() as i32);
+            if direction != Some(step) {
+                turns += 1;
+                direction = Some(step);
+            }
+            end += 1;
+        }
+
+        if end - start >= 3 {
+            let shifted = positions[start..end].iter().flatten().filter(|p| p.2).count();
+            let guesses = spatial_guesses(end - start, turns) * shift_variations(shifted, end - start - shifted);
+            push_match(matches, start, end, guesses, Pattern::Spatial { turns });
+        }
+        start = if end - start >= 2 { end - 1 } else { start + 1 };
+    }
+}
+
+fn spatial_guesses(length: usize, turns: usize) -> f64 {
+    let mut guesses = 0.0;
+    for i in 2..=length {
+        for j in 1..=turns.min(i - 1) {
+            guesses += binomial(i - 1, j - 1) * KEYBOARD_STARTING_POSITIONS * KEYBOARD_AVERAGE_DEGREE.powi(j as i32);
+        }
+    }
+    guesses
+}
+
+fn shift_variations(shifted: usize, unshifted: usize) -> f64 {
+    match (shifted, unshifted) {
+        (0, _) => 1.0,
+        (_, 0) => 2.0,
+        _ => (1..=shifted.min(unshifted)).map(|i| binomial(shifted + unshifted, i)).sum(),
+    }
+}
+
+/// Runs like "abc", "7531" or "zyx" with a constant step.
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 1 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let mut end = start + 2;
+        while end < chars.len() && chars[end] as i64 - chars[end - 1] as i64 == delta {
+            end += 1;
+        }
+
+        if end - start >= 3 && (1..=5).contains(&delta.abs()) {
+            let first = chars[start];
+            let base = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction = if delta < 0 { 2.0 } else { 1.0 };
+            push_match(matches, start, end, base * (end - start) as f64 * direction, Pattern::Sequence);
+        }
+        start = end - 1;
+    }
+}
+
+/// Repeated runs like "aaa" or "abcabc", costed as the repeated unit times
+/// the number of repetitions.
+fn repeat_matches(chars: &[char], user_words: &HashMap<String, usize>, matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start < chars.len() {
+        let mut best: Option<(usize, usize)> = None;
+        for unit in 1..=(chars.len() - start) / 2 {
+            let mut repeats = 1;
+            while start + (repeats + 1) * unit <= chars.len()
+                && chars[start + repeats * unit..start + (repeats + 1) * unit] == chars[start..start + unit]
+            {
+                repeats += 1;
+            }
+            let covered = unit * repeats;
+            if repeats >= 2 && covered >= 3 && best.is_none_or(|(u, r)| covered > u * r) {
+                best = Some((unit, repeats));
+            }
+        }
+
+        match best {
+            Some((unit, repeats)) => {
+                let end = start + unit * repeats;
+                let (unit_log_guesses, _) = most_guessable(&chars[start..start + unit], user_words);
+                let guesses = 10f64.powf(unit_log_guesses) * repeats as f64;
+                push_match(matches, start, end, guesses, Pattern::Repeat { unit_length: unit });
+                // Skip past the run so shorter overlapping repeats aren't
+                // re-analysed from every position inside it
+                start = end;
+            }
+            None => start += 1,
+        }
+    }
+}
+
+/// Years and day/month/year dates, with or without separators.
+fn date_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let reference_year = chrono::Utc::now().year();
+    let year_space = |year: i32| ((year - reference_year).abs() as f64).max(MIN_YEAR_SPACE);
+
+    for start in 0..chars.len() {
+        for end in start + 4..=(start + 10).min(chars.len()) {
+            let text: String = chars[start..end].iter().collect();
+            if text.chars().all(|c| c.is_ascii_digit()) {
+                if end - start == 4 {
+                    if let Ok(year) = text.parse::<i32>() {
+                        if (1900..=2050).contains(&year) {
+                            push_match(matches, start, end, year_space(year), Pattern::Date { year_only: true });
+                        }
+                    }
+                } else if let Some(year) = unseparated_date(&text) {
+                    push_match(matches, start, end, year_space(year) * 365.0, Pattern::Date { year_only: false });
+                }
+            } else if let Some(year) = separated_date(&text) {
+                push_match(matches, start, end, year_space(year) * 365.0 * 4.0, Pattern::Date { year_only: false });
+            }
+        }
+    }
+}
+
+/// The year of a date written as three digit groups in any common order.
+fn parse_date(parts: [&str; 3]) -> Option<i32> {
+    let numbers: Vec<u32> = parts.iter().map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    // (year, month, day) positions for y-m-d, d-m-y and m-d-y
+    let orders = [(0, 1, 2), (2, 1, 0), (2, 0, 1)];
+    orders.iter().find_map(|&(y, m, d)| {
+        let year_digits = parts[y].len();
+        let valid = (1..=12).contains(&numbers[m])
+            && (1..=31).contains(&numbers[d])
+            && parts[m].len() <= 2
+            && parts[d].len() <= 2;
+        let year = match year_digits {
+            2 if numbers[y] > 50 => 1900 + numbers[y] as i32,
+            2 => 2000 + numbers[y] as i32,
+            4 if (1000..=2050).contains(&numbers[y]) => numbers[y] as i32,
+            _ => return None,
+        };
+        valid.then_some(year)
+    })
+}
+
+fn unseparated_date(text: &str) -> Option<i32> {
+    if !(5..=8).contains(&text.len()) {
+        return None;
+    }
+    for first in 1..text.len() {
+        for second in first + 1..text.len() {
+            if let Some(year) = parse_date([&text[..first], &text[first..second], &text[second..]]) {
+                return Some(year);
+            }
+        }
+    }
+    None
+}
+
+fn separated_date(text: &str) -> Option<i32> {
+    let separator = text.chars().find(|c| !c.is_ascii_digit())?;
+    if !"/-._ \\".contains(separator) {
+        return None;
+    }
+    let parts: Vec<&str> = text.split(separator).collect();
+    match parts.as_slice() {
+        [a, b, c] if [a, b, c].iter().all(|p| !p.is_empty() && p.len() <= 4) => parse_date([a, b, c]),
+        _ => None,
+    }
+}
+
+fn warning_for(matches: &[Match], length: usize) -> Option<&'static str> {
+    let longest = matches.iter().max_by_key(|m| m.end - m.start)?;
+    let whole = longest.end - longest.start == length;
+    Some(match longest.pattern {
+        Pattern::CommonPassword if whole => "This is a very common password",
+        Pattern::CommonPassword => "This is similar to a commonly used password",
+        Pattern::EnglishWord if whole => "A word by itself is easy to guess",
+        Pattern::EnglishWord => "Common words are easy to guess",
+        Pattern::UserInput => "Avoid using the entry's name, username or URL",
+        Pattern::Spatial { turns: 1 } => "Straight rows of keys are easy to guess",
+        Pattern::Spatial { .. } => "Short keyboard patterns are easy to guess",
+        Pattern::Sequence => "Sequences like abc or 6543 are easy to guess",
+        Pattern::Repeat { unit_length: 1 } => "Repeats like \"aaa\" are easy to guess",
+        Pattern::Repeat { .. } => "Repeats like \"abcabc\" are barely harder to guess than \"abc\"",
+        Pattern::Date { year_only: true } => "Recent years are easy to guess",
+        Pattern::Date { .. } => "Dates are often easy to guess",
+    })
+}
+
+fn display_time(seconds: f64) -> String {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = MINUTE * 60.0;
+    const DAY: f64 = HOUR * 24.0;
+    const MONTH: f64 = DAY * 31.0;
+    const YEAR: f64 = MONTH * 12.0;
+    const CENTURY: f64 = YEAR * 100.0;
+
+    let (amount, unit) = match seconds {
+        s if s < 1.0 => return "less than a second".to_string(),
+        s if s < MINUTE => (s, "second"),
+        s if s < HOUR => (s / MINUTE, "minute"),
+        s if s < DAY => (s / HOUR, "hour"),
+        s if s < MONTH => (s / DAY, "day"),
+        s if s < YEAR => (s / MONTH, "month"),
+        s if s < CENTURY => (s / YEAR, "year"),
+        _ => return "centuries".to_string(),
+    };
+    let amount = amount.round() as u64;
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_weak(password: &str, warning: &str) {
+        let strength = estimate(password, &["alice", "github"]);
+        assert!(strength.score <= 1, "{} scored {}", password, strength.score);
+        assert_eq!(strength.warning, Some(warning), "{}", password);
+    }
+
+    #[test]
+    fn common_passwords_and_words_are_weak() {
+        assert_weak("password", "This is a very common password");
+        assert_weak("monkey", "This is a very common password");
+        assert_weak("village", "A word by itself is easy to guess");
+        assert_weak("village1", "Common words are easy to guess");
+        assert_weak("alice2024", "Avoid using the entry's name, username or URL");
+    }
+
+    #[test]
+    fn l33t_spellings_are_weak() {
+        assert_weak("p@ssw0rd", "This is a very common password");
+        assert_weak("P4$$w0rd", "This is a very common password");
+        assert_weak("v1ll4g3", "A word by itself is easy to guess");
+    }
+
+    #[test]
+    fn keyboard_walks_sequences_and_repeats_are_weak() {
+        assert_weak("zxcvbnm,./", "Straight rows of keys are easy to guess");
+        assert_weak("abcdefgh", "Sequences like abc or 6543 are easy to guess");
+        assert_weak("13579", "Sequences like abc or 6543 are easy to guess");
+        assert_weak("aaaaaaa", "Repeats like \"aaa\" are easy to guess");
+        assert_weak("abcabcabc", "Repeats like \"abcabc\" are barely harder to guess than \"abc\"");
+    }
+
+    #[test]
+    fn dates_are_weak() {
+        assert_weak("19/04/1991", "Dates are often easy to guess");
+        assert_weak("1991-04-19", "Dates are often easy to guess");
+        assert_weak("04191991", "Dates are often easy to guess");
+    }
+
+    #[test]
+    fn random_passwords_and_long_passphrases_are_strong() {
+        for password in ["X9#kq2!Lm7@vR4$z", "correcthorsebatterystaple", "abandon-ability-able-about-above-absent"] {
+            let strength = estimate(password, &[]);
+            assert_eq!(strength.score, 4, "{}", password);
+            assert_eq!(strength.warning, None);
+        }
+
+        let generated = crate::generator::generate_password(&crate::generator::PasswordPolicy::default()).unwrap();
+        assert_eq!(estimate(&generated.secret, &[]).score, 4);
+    }
+
+    #[test]
+    fn scores_grow_with_guesses() {
+        // Every added random character makes the password harder to guess
+        let password = "kT7#pQ2xWm9!";
+        let mut previous = estimate("", &[]);
+        for end in 1..=password.len() {
+            let strength = estimate(&password[..end], &[]);
+            assert!(strength.guesses > previous.guesses, "{}", &password[..end]);
+            assert!(strength.score >= previous.score, "{}", &password[..end]);
+            previous = strength;
+        }
+
+        let scores: Vec<u8> = (0..=12).map(|exponent| score_for(10f64.powi(exponent) * 2.0)).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!((scores[0], scores[12]), (0, 4));
+    }
+}
@@ -1,140 +1,332 @@
 use anyhow::Result;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
 use std::{
     io,
+    path::PathBuf,
     time::{Duration, Instant},
     sync::atomic::{AtomicBool, Ordering},
 };
 
 mod config;
+mod crypto;
+mod generator;
+mod migration;
+mod otp;
+mod recovery;
+mod strength;
 mod vault;
 mod clipboard;
+mod query;
 mod search;
 
-use vault::PasswordEntry;
+use clipboard::ClipboardManager;
+use search::{Highlights, SearchEngine};
+use strength::Strength;
+use vault::{PasswordEntry, UsageKind, VaultManager};
+use zeroize::Zeroize;
+
+/// How often `App::on_tick` runs, whether or not keys are pressed
+const TICK_RATE: Duration = Duration::from_millis(250);
+/// How long a toast stays in the status bar
+const TOAST_DURATION: Duration = Duration::from_secs(3);
+/// How often the vault file is checked for changes saved by the CLI or
+/// another TUI
+const VAULT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Separates the fields of the Add/Edit input, so generated passwords
+/// can't contain it
+const FIELD_SEPARATOR: char = '|';
 
 #[derive(Clone)]
 pub struct App {
+    pub config: config::Config,
     pub should_quit: bool,
     pub search_query: String,
-    pub entries: Vec<PasswordEntry>,
+    pub vault: VaultManager,
     pub filtered_entries: Vec<PasswordEntry>,
+    /// Matched characters of each filtered entry, to highlight
+    pub highlights: Vec<Highlights>,
     pub selected_index: usize,
     pub list_state: ListState,
     pub mode: AppMode,
     pub status_message: String,
     pub status_timer: Option<Instant>,
+    pub master_input: String,
+    pub pending_master: Option<String>,
+    pub latest_backup: Option<PathBuf>,
+    pub salvage_count: usize,
+    pub clipboard: ClipboardManager,
+    pub otp_countdown: Option<OtpCountdown>,
+    /// When the clipboard clearing helper will clear the last copy
+    pub clipboard_clears_at: Option<Instant>,
+    /// When the user last pressed a key, for `auto_lock_minutes`
+    pub last_input: Instant,
+    pub last_vault_check: Instant,
+}
+
+/// The one-time code last copied, counting down until it stops being valid
+#[derive(Clone)]
+pub struct OtpCountdown {
+    pub entry_name: String,
+    pub expires_at: Instant,
 }
 
 #[derive(Clone, PartialEq)]
 pub enum AppMode {
+    Unlock,
+    /// Locked after being idle; unlocking returns to the search
+    Locked,
+    Recovery,
     Search,
     Add,
     Edit,
     Delete,
 }
 
-impl Default for App {
-    fn default() -> App {
+impl App {
+    pub fn new(vault_override: Option<&std::path::Path>) -> Result<Self> {
+        let config = config::Config::load()?.with_vault_override(vault_override);
+        let mut vault = VaultManager::new(&config)?;
+        if let Some(home) = dirs::home_dir().filter(|_| !config.vault_overridden) {
+            vault.relocate_legacy_vault(&home)?;
+        }
+        Ok(Self::with_vault(config, vault))
+    }
+
+    fn with_vault(config: config::Config, vault: VaultManager) -> Self {
+        let clipboard = ClipboardManager::new(&config);
+
+        let status_message = if let Some(legacy_path) = vault.relocated_from() {
+            format!(
+                "Moved vault from {} to {}. Enter your master password",
+                legacy_path.display(),
+                vault.path().display()
+            )
+        } else if vault.exists() {
+            "Enter your master password to unlock the vault".to_string()
+        } else {
+            "No vault found: choose a master password for your new vault".to_string()
+        };
+
         App {
+            config,
             should_quit: false,
             search_query: String::new(),
-            entries: Vec::new(),
+            vault,
             filtered_entries: Vec::new(),
+            highlights: Vec::new(),
             selected_index: 0,
             list_state: ListState::default(),
-            mode: AppMode::Search,
-            status_message: String::new(),
+            mode: AppMode::Unlock,
+            status_message,
             status_timer: None,
+            master_input: String::new(),
+            pending_master: None,
+            latest_backup: None,
+            salvage_count: 0,
+            clipboard,
+            otp_countdown: None,
+            clipboard_clears_at: None,
+            last_input: Instant::now(),
+            last_vault_check: Instant::now(),
         }
     }
-}
 
-impl App {
-    pub fn new() -> Result<Self> {
-        let mut app = Self::default();
-        app.load_entries()?;
-        app.filter_entries();
-        Ok(app)
+    fn submit_master_password(&mut self) {
+        let password = std::mem::take(&mut self.master_input);
+        if password.is_empty() {
+            self.status_message = "Master password cannot be empty".to_string();
+            return;
+        }
+
+        // A new vault needs the password typed twice before it is created
+        if !self.vault.exists() {
+            match self.pending_master.take() {
+                None => {
+                    self.pending_master = Some(password);
+                    self.status_message = "Confirm your new master password".to_string();
+                    return;
+                }
+                Some(first) if first != password => {
+                    self.status_message = "Passwords did not match, try again".to_string();
+                    return;
+                }
+                Some(_) => {}
+            }
+        }
+
+        let was_locked = self.mode == AppMode::Locked;
+        match self.vault.unlock(&password) {
+            Ok(upgrade) => {
+                self.mode = AppMode::Search;
+                self.filter_entries();
+
+                self.status_message = if let Some(upgrade) = upgrade {
+                    format!(
+                        "Vault upgraded from format v{} to v{} (backup: {})",
+                        upgrade.from_version,
+                        migration::CURRENT_FORMAT_VERSION,
+                        upgrade.backup_path.display()
+                    )
+                } else if was_locked {
+                    "Vault unlocked".to_string()
+                } else {
+                    format!("Vault unlocked ({} entries)", self.vault.entries().len())
+                };
+                self.status_timer = Some(Instant::now() + TOAST_DURATION);
+            }
+            Err(_) if self.vault.corruption().is_some() => {
+                self.enter_recovery();
+            }
+            Err(e) => {
+                self.status_message = format!("Unlock failed: {}", e);
+            }
+        }
     }
 
-    fn load_entries(&mut self) -> Result<()> {
-        // Load entries from vault file in user's home directory
-        let vault_path = self.get_vault_path()?;
-        
-        if vault_path.exists() {
-            let vault_content = std::fs::read_to_string(&vault_path)?;
-            
-            // Parse as array format (new simplified format)
-            if let Ok(entries) = serde_json::from_str::<Vec<PasswordEntry>>(&vault_content) {
-                self.entries = entries;
-                println!("[TUI] Loaded {} entries from {:?}", self.entries.len(), vault_path);
-            } else {
-                println!("[TUI] Could not parse vault file, starting with empty vault");
-                self.entries = Vec::new();
+    /// Runs every `TICK_RATE`: expires toasts and countdowns, locks the
+    /// vault when idle and picks up changes saved by other processes.
+    fn on_tick(&mut self) {
+        let now = Instant::now();
+        if self.status_timer.is_some_and(|timer| now >= timer) {
+            self.status_message.clear();
+            self.status_timer = None;
+        }
+        self.expire_otp_countdown();
+        if self.clipboard_clears_at.is_some_and(|clears_at| now >= clears_at) {
+            self.clipboard_clears_at = None;
+        }
+
+        self.lock_if_idle();
+        if now.duration_since(self.last_vault_check) >= VAULT_CHECK_INTERVAL {
+            self.last_vault_check = now;
+            self.reload_changed_vault();
+        }
+    }
+
+    /// Shows `message` in the status bar for `TOAST_DURATION`.
+    fn toast(&mut self, message: String) {
+        self.status_message = message;
+        self.status_timer = Some(Instant::now() + TOAST_DURATION);
+    }
+
+    /// Only done while searching, so an entry being edited or deleted
+    /// can't move out from under the selection.
+    fn reload_changed_vault(&mut self) {
+        if self.mode != AppMode::Search {
+            return;
+        }
+        match self.vault.reload_if_changed() {
+            Ok(true) => {
+                self.filter_entries();
+                self.toast(format!("Vault changed on disk, reloaded ({} entries)", self.vault.entries().len()));
+            }
+            Ok(false) => {}
+            Err(e) => {
+                self.status_message = format!("Could not reload the changed vault: {}", e);
+                self.status_timer = None;
             }
-        } else {
-            // Create empty vault if it doesn't exist
-            self.entries = Vec::new();
-            println!("[TUI] No vault file found at {:?}, starting with empty vault", vault_path);
         }
-        Ok(())
     }
 
-    fn save_entries(&self) {
-        // Save entries to vault file in user's home directory
-        if let Ok(vault_path) = self.get_vault_path() {
-            if let Ok(entries_json) = serde_json::to_string_pretty(&self.entries) {
-                if let Err(e) = std::fs::write(&vault_path, entries_json) {
-                    eprintln!("Error saving entries to {:?}: {}", vault_path, e);
-                } else {
-                    println!("[TUI] Saved {} entries to {:?}", self.entries.len(), vault_path);
-                }
+    /// Locks the vault once `auto_lock_minutes` have passed without input.
+    fn lock_if_idle(&mut self) {
+        let minutes = self.config.auto_lock_minutes;
+        if minutes == 0 || !self.vault.is_unlocked() {
+            return;
+        }
+        if self.last_input.elapsed() >= Duration::from_secs(minutes * 60) {
+            self.lock_vault();
+        }
+    }
+
+    /// Wipes every decrypted entry from memory and asks for the master
+    /// password again. The search query is kept, but Add/Edit input may
+    /// hold a new password and is dropped.
+    fn lock_vault(&mut self) {
+        if matches!(self.mode, AppMode::Add | AppMode::Edit) {
+            self.search_query.zeroize();
+        }
+        for entry in &mut self.filtered_entries {
+            entry.wipe();
+        }
+        self.filtered_entries.clear();
+        self.highlights.clear();
+        self.list_state.select(None);
+        self.vault.lock();
+
+        self.mode = AppMode::Locked;
+        self.master_input.zeroize();
+        self.otp_countdown = None;
+        self.clipboard_clears_at = None;
+        self.status_timer = None;
+        self.status_message = format!(
+            "Locked after {} minutes without input. Enter your master password",
+            self.config.auto_lock_minutes
+        );
+    }
+
+    fn enter_recovery(&mut self) {
+        self.mode = AppMode::Recovery;
+        self.latest_backup = self.vault.backups().ok().and_then(|backups| backups.into_iter().next());
+        self.salvage_count = self.vault.salvageable_entries().len();
+        self.status_message.clear();
+    }
+
+    fn restore_latest_backup(&mut self) {
+        let Some(backup) = self.latest_backup.clone() else {
+            self.status_message = "No backup available to restore".to_string();
+            return;
+        };
+
+        match self.vault.restore_backup(&backup) {
+            Ok(_) => {
+                self.mode = AppMode::Unlock;
+                self.status_message = format!(
+                    "Restored {}, corrupt vault kept alongside it. Enter your master password",
+                    backup.display()
+                );
+            }
+            Err(e) => {
+                self.status_message = format!("Restore failed: {}", e);
             }
-        } else {
-            eprintln!("Error getting vault path for saving");
         }
     }
 
-    fn get_vault_path(&self) -> Result<std::path::PathBuf> {
-        // Get user's home directory
-        let home_dir = dirs::home_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
-        
-        // Create .passman directory if it doesn't exist
-        let passman_dir = home_dir.join(".passman");
-        if !passman_dir.exists() {
-            std::fs::create_dir_all(&passman_dir)?;
+    fn salvage_entries(&mut self) {
+        match self.vault.recover_salvaged_entries() {
+            Ok(preserved) => {
+                self.mode = AppMode::Search;
+                self.filter_entries();
+                self.status_message = format!(
+                    "Recovered {} entries (corrupt vault kept at {})",
+                    self.vault.entries().len(),
+                    preserved.display()
+                );
+            }
+            Err(e) => {
+                self.status_message = format!("Salvage failed: {}", e);
+            }
         }
-        
-        // Return path to vault.json in .passman directory
-        Ok(passman_dir.join("vault.json"))
     }
 
     fn filter_entries(&mut self) {
-        if self.search_query.is_empty() {
-            self.filtered_entries = self.entries.clone();
-        } else {
-            self.filtered_entries = self.entries
-                .iter()
-                .filter(|entry| {
-                    entry.name.to_lowercase().contains(&self.search_query.to_lowercase())
-                })
-                .cloned()
-                .collect();
-        }
+        let results = SearchEngine::new(self.config.sort).search(self.vault.entries(), self.vault.usage(), &self.search_query);
+        (self.filtered_entries, self.highlights) = results
+            .into_iter()
+            .map(|result| (result.entry, result.highlights))
+            .unzip();
         
         // Reset selection if out of bounds
         if self.selected_index >= self.filtered_entries.len() {
@@ -172,12 +364,14 @@ impl App {
     }
 
     fn copy_password(&mut self) {
-        if let Some(entry) = self.get_selected_entry() {
+        if let Some(entry) = self.get_selected_entry().cloned() {
             // Copy password to clipboard
-            if let Err(e) = self.copy_to_clipboard(&entry.password) {
+            if let Err(e) = self.clipboard.copy_with_auto_clear(&entry.password) {
                 self.status_message = format!("Error copying to clipboard: {}", e);
             } else {
-                self.status_message = format!("Password for '{}' copied to clipboard", entry.name);
+                self.status_message = format!("Password for '{}' copied to clipboard{}", entry.name, self.clears_in());
+                self.start_clipboard_countdown();
+                self.record_use(&entry, UsageKind::Copy);
             }
             self.status_timer = Some(Instant::now() + Duration::from_secs(1));
             // Auto-quit after copying password
@@ -185,121 +379,333 @@ impl App {
         }
     }
 
-    fn copy_to_clipboard(&self, text: &str) -> Result<()> {
-        // Try to copy to clipboard using system commands
-        if self.try_wayland_copy(text) || self.try_x11_copy(text) {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Failed to copy to clipboard"))
+    /// Copies the selected entry's current one-time code and starts the
+    /// countdown of its validity window.
+    fn copy_otp_code(&mut self) {
+        let Some(mut entry) = self.get_selected_entry().cloned() else { return };
+        let Some(otp) = entry.otp.as_mut() else {
+            self.status_message = format!("'{}' has no one-time code", entry.name);
+            return;
+        };
+
+        let code = match otp.next_code() {
+            Ok(code) => code,
+            Err(e) => {
+                self.status_message = format!("Could not generate one-time code: {}", e);
+                return;
+            }
+        };
+        // HOTP codes advance the counter, which is saved together with the
+        // use before the code leaves the app
+        let hotp = otp.is_hotp();
+        if hotp {
+            let saved = self.vault.update_entry(entry.clone())
+                .and_then(|()| self.vault.record_use(&entry.id, UsageKind::Copy))
+                .and_then(|()| self.vault.save_entries());
+            if let Err(e) = saved {
+                self.status_message = format!("Error saving vault: {}", e);
+                return;
+            }
+            self.filter_entries();
+        }
+
+        if let Err(e) = self.clipboard.copy_with_auto_clear(&code.code) {
+            self.status_message = format!("Error copying to clipboard: {}", e);
+            return;
         }
+        self.toast(format!("One-time code for '{}' copied to clipboard", entry.name));
+        self.start_clipboard_countdown();
+        if !hotp {
+            self.record_use(&entry, UsageKind::Copy);
+        }
+        self.otp_countdown = code.remaining_seconds.map(|seconds| OtpCountdown {
+            entry_name: entry.name.clone(),
+            expires_at: Instant::now() + Duration::from_secs(seconds),
+        });
     }
 
-    fn try_wayland_copy(&self, text: &str) -> bool {
-        std::process::Command::new("wl-copy")
-            .arg(text)
-            .output()
-            .is_ok()
+    /// Saves a use of the entry for frecency ranking. The list isn't
+    /// re-sorted straight away, so the selection stays put.
+    fn record_use(&mut self, entry: &PasswordEntry, kind: UsageKind) {
+        if let Err(e) = self.vault.record_use(&entry.id, kind).and_then(|()| self.vault.save_entries()) {
+            self.status_message = format!("{} (could not save usage: {})", self.status_message, e);
+        }
     }
 
-    fn try_x11_copy(&self, text: &str) -> bool {
-        std::process::Command::new("xclip")
-            .args(&["-selection", "clipboard"])
-            .stdin(std::process::Stdio::piped())
-            .spawn()
-            .and_then(|mut child| {
-                use std::io::Write;
-                child.stdin.as_mut().unwrap().write_all(text.as_bytes())?;
-                child.wait()?;
-                Ok(())
-            })
-            .is_ok()
+    /// Ends the countdown of the copied one-time code once it is no longer
+    /// valid.
+    fn expire_otp_countdown(&mut self) {
+        let Some(countdown) = &self.otp_countdown else { return };
+        if Instant::now() >= countdown.expires_at {
+            let message = format!("One-time code for '{}' has expired", countdown.entry_name);
+            self.otp_countdown = None;
+            self.toast(message);
+        }
+    }
+
+    fn start_clipboard_countdown(&mut self) {
+        let timeout = self.clipboard.timeout();
+        self.clipboard_clears_at = Some(Instant::now() + timeout).filter(|_| !timeout.is_zero());
+    }
+
+    /// ", cleared in 20s" for status messages, unless clearing is off.
+    fn clears_in(&self) -> String {
+        match self.clipboard.timeout().as_secs() {
+            0 => String::new(),
+            seconds => format!(", cleared in {}s", seconds),
+        }
     }
 
     fn add_entry(&mut self) {
         self.mode = AppMode::Add;
-        self.status_message = "Add mode: Enter name and password (format: name|password)".to_string();
+        self.status_message = "Add mode: name|password[|username|url|tags|otp] (Ctrl+G: generate password)".to_string();
     }
 
     fn edit_entry(&mut self) {
         if self.get_selected_entry().is_some() {
             self.mode = AppMode::Edit;
-            self.status_message = "Edit mode: Enter new name and password (format: name|password)".to_string();
+            self.status_message = "Edit mode: name|password[|username|url|tags|otp] (Ctrl+G: generate password)".to_string();
+        }
+    }
+
+    /// Fills the password field of the Add/Edit input with a generated
+    /// password, or a passphrase if those are the configured default,
+    /// keeping whatever else has been typed.
+    fn generate_password_input(&mut self) {
+        let (kind, result) = if self.config.passphrase.default {
+            ("passphrase", generator::generate_passphrase(&self.config.passphrase))
+        } else {
+            ("password", generator::generate_password_without(&self.config.generator, &FIELD_SEPARATOR.to_string()))
+        };
+        let generated = match result {
+            Ok(generated) => generated,
+            Err(e) => {
+                self.status_message = format!("Could not generate {}: {}", kind, e);
+                return;
+            }
+        };
+
+        // Passphrases can still pick it up from the separator or wordlist
+        if generated.secret.contains(FIELD_SEPARATOR) {
+            self.status_message = format!(
+                "The generated {} contains '{}', which separates the fields; change the passphrase separator or wordlist",
+                kind, FIELD_SEPARATOR
+            );
+            return;
+        }
+
+        let mut fields: Vec<String> = self.search_query.split(FIELD_SEPARATOR).map(str::to_string).collect();
+        if fields.len() < 2 {
+            fields.resize(2, String::new());
         }
+        fields[1] = generated.secret;
+        self.search_query = fields.join(&FIELD_SEPARATOR.to_string());
+        self.status_message = format!(
+            "Generated a {} with {:.0} bits of entropy (Ctrl+G for another)",
+            kind, generated.entropy_bits
+        );
     }
 
     fn delete_entry(&mut self) {
-        if let Some(entry_name) = self.get_selected_entry().map(|e| e.name.clone()) {
+        if self.get_selected_entry().is_some() {
             self.mode = AppMode::Delete;
             self.status_message = "Delete mode: Press 'y' to confirm, 'n' to cancel".to_string();
         }
     }
 
+    /// Strength of the password being typed in Add/Edit mode.
+    fn input_strength(&self) -> Option<Strength> {
+        if !matches!(self.mode, AppMode::Add | AppMode::Edit) {
+            return None;
+        }
+        let fields: Vec<&str> = self.search_query.split(FIELD_SEPARATOR).map(str::trim).collect();
+        let password = fields.get(1).filter(|password| !password.is_empty())?;
+        // The name, username and URL are the first things an attacker tries
+        let user_inputs: Vec<&str> = [0, 2, 3]
+            .iter()
+            .filter_map(|&i| fields.get(i).copied())
+            .filter(|field| !field.is_empty())
+            .collect();
+        Some(strength::estimate(password, &user_inputs))
+    }
+
+    /// Checks the typed password against `min_password_score`, explaining a
+    /// rejection in the status bar.
+    fn meets_minimum_strength(&mut self) -> bool {
+        let minimum = self.config.min_password_score;
+        match self.input_strength() {
+            Some(strength) if strength.score < minimum => {
+                self.status_message = format!(
+                    "Password scores {}/4, below the minimum of {}: {}",
+                    strength.score,
+                    minimum,
+                    strength.warning.unwrap_or("choose a stronger one")
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
     fn process_input(&mut self, input: &str) {
         match self.mode {
             AppMode::Add => {
-                if let Some((name, password)) = input.split_once('|') {
-                    let entry = PasswordEntry {
-                        name: name.trim().to_string(),
-                        password: password.trim().to_string(),
-                    };
-                    self.entries.push(entry);
-                    self.save_entries();
+                if let Some((name, password, extra)) = split_input_fields(input) {
+                    if !self.meets_minimum_strength() {
+                        return;
+                    }
+                    let mut entry = PasswordEntry::new(name, password);
+                    if let Err(e) = apply_optional_fields(&mut entry, &extra) {
+                        self.status_message = e.to_string();
+                        return;
+                    }
+                    let result = self.vault.add_entry(entry).and_then(|()| self.vault.save_entries());
                     self.filter_entries();
                     self.mode = AppMode::Search;
-                    self.status_message = "Entry added successfully!".to_string();
+                    match result {
+                        Ok(()) => self.toast("Entry added successfully!".to_string()),
+                        Err(e) => self.status_message = format!("Error saving vault: {}", e),
+                    }
                 } else {
-                    self.status_message = "Invalid format. Use: name|password".to_string();
+                    self.status_message = "Invalid format. Use: name|password[|username|url|tags|otp]".to_string();
                 }
             }
             AppMode::Edit => {
-                if let Some((name, password)) = input.split_once('|') {
-                    if let Some(entry) = self.filtered_entries.get_mut(self.selected_index) {
-                        let old_name = entry.name.clone();
-                        entry.name = name.trim().to_string();
-                        entry.password = password.trim().to_string();
-                        
-                        // Update in main entries list
-                        if let Some(main_entry) = self.entries.iter_mut().find(|e| e.name == old_name) {
-                            main_entry.name = name.trim().to_string();
-                            main_entry.password = password.trim().to_string();
+                if let Some((name, password, extra)) = split_input_fields(input) {
+                    if let Some(mut entry) = self.get_selected_entry().cloned() {
+                        // Only a new password has to meet the minimum
+                        if entry.password != password && !self.meets_minimum_strength() {
+                            return;
+                        }
+                        entry.name = name.to_string();
+                        entry.password = password.to_string();
+                        if let Err(e) = apply_optional_fields(&mut entry, &extra) {
+                            self.status_message = e.to_string();
+                            return;
                         }
-                        
-                        self.save_entries();
+
+                        let result = self.vault.update_entry(entry).and_then(|()| self.vault.save_entries());
+                        self.filter_entries();
                         self.mode = AppMode::Search;
-                        self.status_message = "Entry updated successfully!".to_string();
+                        match result {
+                            Ok(()) => self.toast("Entry updated successfully!".to_string()),
+                            Err(e) => self.status_message = format!("Error saving vault: {}", e),
+                        }
                     }
                 } else {
-                    self.status_message = "Invalid format. Use: name|password".to_string();
+                    self.status_message = "Invalid format. Use: name|password[|username|url|tags|otp]".to_string();
                 }
             }
             AppMode::Delete => {
                 match input.to_lowercase().as_str() {
                     "y" | "yes" => {
-                        if let Some(entry_name) = self.get_selected_entry().map(|e| e.name.clone()) {
-                            self.entries.retain(|e| e.name != entry_name);
-                            self.save_entries();
+                        if let Some(entry_id) = self.get_selected_entry().map(|e| e.id.clone()) {
+                            let result = self.vault.delete_entry(&entry_id).and_then(|()| self.vault.save_entries());
                             self.filter_entries();
                             self.mode = AppMode::Search;
-                            self.status_message = "Entry deleted successfully!".to_string();
+                            match result {
+                                Ok(()) => self.toast("Entry deleted successfully!".to_string()),
+                                Err(e) => self.status_message = format!("Error saving vault: {}", e),
+                            }
                         }
                     }
                     "n" | "no" => {
                         self.mode = AppMode::Search;
-                        self.status_message = "Delete cancelled".to_string();
+                        self.toast("Delete cancelled".to_string());
                     }
                     _ => {
                         self.status_message = "Press 'y' to confirm or 'n' to cancel".to_string();
                     }
                 }
             }
-            AppMode::Search => {
-                // This shouldn't happen in search mode
+            AppMode::Unlock | AppMode::Locked | AppMode::Recovery | AppMode::Search => {
+                // These modes don't take line input
             }
         }
     }
+
+    /// Commands are Ctrl chords while searching, so every character typed,
+    /// including the `:` and `-` of scoped terms, goes into the query.
+    fn handle_search_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            match key.code {
+                KeyCode::Char('q') => self.should_quit = true,
+                KeyCode::Char('o') => self.copy_otp_code(),
+                KeyCode::Char('a') => self.add_entry(),
+                KeyCode::Char('e') => self.edit_entry(),
+                KeyCode::Char('d') => self.delete_entry(),
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.search_query.push(c);
+                self.filter_entries();
+            }
+            KeyCode::Backspace => {
+                self.search_query.pop();
+                self.filter_entries();
+            }
+            KeyCode::Down => self.next_entry(),
+            KeyCode::Up => self.previous_entry(),
+            KeyCode::Enter => self.copy_password(),
+            KeyCode::Esc => {
+                self.search_query.clear();
+                self.filter_entries();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Splits `name|password[|username|url|tags|otp]` into the two required fields
+/// and whatever optional fields follow.
+fn split_input_fields(input: &str) -> Option<(&str, &str, Vec<&str>)> {
+    let mut fields = input.split(FIELD_SEPARATOR).map(str::trim);
+    let name = fields.next().filter(|name| !name.is_empty())?;
+    let password = fields.next()?;
+    Some((name, password, fields.collect()))
+}
+
+/// Applies the optional `username|url|tags|otp` fields. Fields that were not
+/// typed are left unchanged, empty ones are cleared.
+fn apply_optional_fields(entry: &mut PasswordEntry, extra: &[&str]) -> Result<()> {
+    let non_empty = |field: &str| Some(field.to_string()).filter(|f| !f.is_empty());
+
+    if let Some(username) = extra.first() {
+        entry.username = non_empty(username);
+    }
+    if let Some(url) = extra.get(1) {
+        entry.url = non_empty(url);
+    }
+    if let Some(tags) = extra.get(2) {
+        entry.tags = vault::parse_tags(tags);
+    }
+    if let Some(otp) = extra.get(3) {
+        entry.otp = non_empty(otp).map(|otp| otp::OtpSecret::parse(&otp)).transpose()?;
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
+    // Same vault resolution as the CLI: --vault, PASSMAN_VAULT, config.toml
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == clipboard::CLEAR_HELPER_ARG) {
+        return clipboard::run_clear_helper(args.get(i + 1).map(String::as_str));
+    }
+    let vault_override = match args.iter().position(|arg| arg == "--vault") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                eprintln!("Error: --vault requires a path");
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
     // Setup panic handler to restore terminal
     let original_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
@@ -337,7 +743,7 @@ fn main() -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app
-    let mut app = App::new()?;
+    let mut app = App::new(vault_override.as_deref())?;
     let res = run_app(&mut terminal, &mut app);
 
     // Always restore terminal, even on panic
@@ -381,45 +787,66 @@ impl Drop for TerminalGuard {
 }
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
+    let mut last_tick = Instant::now();
     loop {
         terminal.draw(|f| ui(f, app))?;
 
+        // Wait for input, but no longer than the next tick is due
+        let timeout = TICK_RATE.saturating_sub(last_tick.elapsed());
+        if !event::poll(timeout)? {
+            app.on_tick();
+            last_tick = Instant::now();
+            continue;
+        }
         if let Event::Key(key) = event::read()? {
             if key.kind == KeyEventKind::Press {
+                app.last_input = Instant::now();
                 match app.mode {
-                    AppMode::Search => {
+                    AppMode::Unlock | AppMode::Locked => {
                         match key.code {
-                            KeyCode::Char('q') => {
+                            KeyCode::Enter => app.submit_master_password(),
+                            KeyCode::Esc => {
                                 app.should_quit = true;
                                 return Ok(());
                             }
-                            KeyCode::Char('a') => app.add_entry(),
-                            KeyCode::Char('e') => app.edit_entry(),
-                            KeyCode::Char('d') => app.delete_entry(),
                             KeyCode::Char(c) => {
-                                app.search_query.push(c);
-                                app.filter_entries();
+                                app.master_input.push(c);
                             }
                             KeyCode::Backspace => {
-                                app.search_query.pop();
-                                app.filter_entries();
+                                app.master_input.pop();
                             }
-                            KeyCode::Down => app.next_entry(),
-                            KeyCode::Up => app.previous_entry(),
-                            KeyCode::Enter => app.copy_password(),
-                            KeyCode::Esc => {
-                                app.search_query.clear();
-                                app.filter_entries();
+                            _ => {}
+                        }
+                    }
+                    AppMode::Recovery => {
+                        match key.code {
+                            KeyCode::Char('r') => app.restore_latest_backup(),
+                            KeyCode::Char('s') => app.salvage_entries(),
+                            KeyCode::Char('q') | KeyCode::Esc => {
+                                app.should_quit = true;
+                                return Ok(());
                             }
                             _ => {}
                         }
                     }
+                    AppMode::Search => {
+                        app.handle_search_key(key);
+                        if app.should_quit {
+                            return Ok(());
+                        }
+                    }
                     AppMode::Add | AppMode::Edit => {
                         match key.code {
+                            KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                app.generate_password_input();
+                            }
                             KeyCode::Enter => {
                                 let input = app.search_query.clone();
                                 app.process_input(&input);
-                                app.search_query.clear();
+                                // Rejected input stays in place to be corrected
+                                if app.mode == AppMode::Search {
+                                    app.search_query.clear();
+                                }
                             }
                             KeyCode::Esc => {
                                 app.mode = AppMode::Search;
@@ -451,12 +878,9 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<
             }
         }
 
-        // Clear status message after timeout
-        if let Some(timer) = app.status_timer {
-            if Instant::now() >= timer {
-                app.status_message.clear();
-                app.status_timer = None;
-            }
+        if last_tick.elapsed() >= TICK_RATE {
+            app.on_tick();
+            last_tick = Instant::now();
         }
 
         // Check if we should quit (e.g., after copying password)
@@ -489,7 +913,12 @@ fn ui(f: &mut Frame, app: &App) {
     f.render_widget(header, chunks[0]);
 
     // Search bar
-    let search_text = if app.mode == AppMode::Search {
+    let search_text = if app.mode == AppMode::Recovery {
+        "⚠️  Vault could not be loaded: recovery mode".to_string()
+    } else if matches!(app.mode, AppMode::Unlock | AppMode::Locked) {
+        let label = if app.pending_master.is_some() { "Confirm password" } else { "Master password" };
+        format!("🔒 {}: {}", label, "*".repeat(app.master_input.chars().count()))
+    } else if app.mode == AppMode::Search {
         format!("🔍 Search: {}", app.search_query)
     } else {
         format!("📝 {}: {}", 
@@ -497,25 +926,86 @@ fn ui(f: &mut Frame, app: &App) {
                 AppMode::Add => "Add Entry",
                 AppMode::Edit => "Edit Entry", 
                 AppMode::Delete => "Delete Entry",
-                AppMode::Search => "Search",
+                AppMode::Unlock | AppMode::Locked | AppMode::Recovery | AppMode::Search => "Search",
             },
             app.search_query
         )
     };
     
     let search_style = match app.mode {
+        AppMode::Unlock | AppMode::Locked => Style::default().fg(Color::Magenta),
+        AppMode::Recovery => Style::default().fg(Color::Red),
         AppMode::Search => Style::default().fg(Color::Green),
         AppMode::Add => Style::default().fg(Color::Blue),
         AppMode::Edit => Style::default().fg(Color::Yellow),
         AppMode::Delete => Style::default().fg(Color::Red),
     };
     
+    let mut input_title = vec![Span::raw("Input")];
+    if let Some(strength) = app.input_strength() {
+        let color = match strength.score {
+            0 | 1 => Color::Red,
+            2 => Color::Yellow,
+            _ => Color::Green,
+        };
+        input_title.push(Span::raw(" - Strength: "));
+        input_title.push(Span::styled(strength.summary(), Style::default().fg(color)));
+        if let Some(warning) = strength.warning {
+            input_title.push(Span::raw(format!(" ({})", warning)));
+        }
+    }
+
     let search = Paragraph::new(search_text)
         .style(search_style)
-        .block(Block::default().borders(Borders::ALL).title("Input"));
+        .block(Block::default().borders(Borders::ALL).title(Line::from(input_title)));
     f.render_widget(search, chunks[1]);
 
-    // List
+    if app.mode == AppMode::Recovery {
+        render_recovery(f, app, chunks[2]);
+    } else if app.mode == AppMode::Locked {
+        render_locked(f, app, chunks[2]);
+    } else {
+        render_entries(f, app, chunks[2]);
+    }
+
+    // Status bar
+    let seconds_until = |at: Instant| at.saturating_duration_since(Instant::now()).as_secs_f64().ceil() as u64;
+    let mut countdowns = Vec::new();
+    if let Some(countdown) = &app.otp_countdown {
+        countdowns.push(format!(
+            "🔑 One-time code for '{}' valid for {}s",
+            countdown.entry_name,
+            seconds_until(countdown.expires_at)
+        ));
+    }
+    if let Some(clears_at) = app.clipboard_clears_at {
+        countdowns.push(format!("📋 Clipboard cleared in {}s", seconds_until(clears_at)));
+    }
+    let status_text = if app.mode == AppMode::Search && !countdowns.is_empty() {
+        if !app.status_message.is_empty() {
+            countdowns.insert(0, app.status_message.clone());
+        }
+        countdowns.join(" | ")
+    } else if !app.status_message.is_empty() {
+        app.status_message.clone()
+    } else {
+        match app.mode {
+            AppMode::Unlock | AppMode::Locked => "Enter: Unlock | Esc: Quit".to_string(),
+            AppMode::Recovery => "r: Restore backup | s: Salvage entries | q: Quit".to_string(),
+            AppMode::Search => "↑↓ Navigate | Enter: Copy | Ctrl+O: Copy code | Ctrl+A: Add | Ctrl+E: Edit | Ctrl+D: Delete | Esc: Clear | Ctrl+Q: Quit".to_string(),
+            AppMode::Add => "Enter: Save | Ctrl+G: Generate password | Esc: Cancel".to_string(),
+            AppMode::Edit => "Enter: Save | Ctrl+G: Generate password | Esc: Cancel".to_string(),
+            AppMode::Delete => "y: Confirm | n: Cancel | Esc: Cancel".to_string(),
+        }
+    };
+    
+    let status = Paragraph::new(status_text)
+        .style(Style::default().fg(Color::White))
+        .block(Block::default().borders(Borders::ALL).title("Status"));
+    f.render_widget(status, chunks[3]);
+}
+
+fn render_entries(f: &mut Frame, app: &App, area: Rect) {
     let items: Vec<ListItem> = app.filtered_entries
         .iter()
         .enumerate()
@@ -527,12 +1017,44 @@ fn ui(f: &mut Frame, app: &App) {
                 Style::default()
             };
 
-            ListItem::new(Line::from(vec![
-                Span::styled(
-                    format!("{}", entry.name),
-                    style.fg(Color::Cyan),
-                ),
-            ]))
+            let no_highlights = Highlights::default();
+            let highlights = app.highlights.get(i).unwrap_or(&no_highlights);
+
+            let mut spans = Vec::new();
+            // The path highlights cover `folder/name` as one string
+            let folder_len = entry.folder.as_ref().map_or(0, |folder| folder.chars().count() + 1);
+            if let Some(folder) = &entry.folder {
+                let indices: Vec<usize> = highlights.path.iter().copied().filter(|&i| i < folder_len).collect();
+                spans.extend(highlighted_spans(&format!("{}/", folder), &indices, Style::default().fg(Color::DarkGray)));
+            }
+            let indices: Vec<usize> = highlights.path.iter().filter_map(|&i| i.checked_sub(folder_len)).collect();
+            spans.extend(highlighted_spans(&entry.name, &indices, style.fg(Color::Cyan)));
+            if let Some(username) = &entry.username {
+                spans.push(Span::styled(" (", Style::default().fg(Color::Gray)));
+                spans.extend(highlighted_spans(username, &highlights.username, Style::default().fg(Color::Gray)));
+                spans.push(Span::styled(")", Style::default().fg(Color::Gray)));
+            }
+            if let Some(url) = &entry.url {
+                spans.push(Span::styled(" - ", Style::default().fg(Color::DarkGray)));
+                spans.extend(highlighted_spans(url, &highlights.url, Style::default().fg(Color::DarkGray)));
+            }
+            if !entry.tags.is_empty() {
+                let tag_style = Style::default().fg(Color::Magenta);
+                spans.push(Span::styled(" [", tag_style));
+                for (t, tag) in entry.tags.iter().enumerate() {
+                    if t > 0 {
+                        spans.push(Span::styled(", ", tag_style));
+                    }
+                    let indices = highlights.tags.get(t).map_or(&[][..], Vec::as_slice);
+                    spans.extend(highlighted_spans(tag, indices, tag_style));
+                }
+                spans.push(Span::styled("]", tag_style));
+            }
+            if entry.otp.is_some() {
+                spans.push(Span::styled(" 🔑", Style::default().fg(Color::Green)));
+            }
+
+            ListItem::new(Line::from(spans))
         })
         .collect();
 
@@ -540,22 +1062,164 @@ fn ui(f: &mut Frame, app: &App) {
         .block(Block::default().borders(Borders::ALL).title("Entries"))
         .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
         .highlight_symbol("▶ ");
-    f.render_stateful_widget(list, chunks[2], &mut app.list_state.clone());
+    f.render_stateful_widget(list, area, &mut app.list_state.clone());
+}
 
-    // Status bar
-    let status_text = if !app.status_message.is_empty() {
-        app.status_message.clone()
-    } else {
-        match app.mode {
-            AppMode::Search => "↑↓ Navigate | Enter: Copy | a: Add | e: Edit | d: Delete | Esc: Clear | q: Quit".to_string(),
-            AppMode::Add => "Enter: Save | Esc: Cancel".to_string(),
-            AppMode::Edit => "Enter: Save | Esc: Cancel".to_string(),
-            AppMode::Delete => "y: Confirm | n: Cancel | Esc: Cancel".to_string(),
+/// Splits `text` into spans in `style`, with the characters at `indices`
+/// picked out as matches.
+fn highlighted_spans(text: &str, indices: &[usize], style: Style) -> Vec<Span<'static>> {
+    let matched = style.fg(Color::LightGreen).add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (i, c) in text.chars().enumerate() {
+        let is_matched = indices.contains(&i);
+        if is_matched != run_matched && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), if run_matched { matched } else { style }));
         }
+        run_matched = is_matched;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, if run_matched { matched } else { style }));
+    }
+    spans
+}
+
+fn render_locked(f: &mut Frame, app: &App, area: Rect) {
+    let mut lines = vec![
+        Line::from(Span::styled("🔒 Vault locked", Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD))),
+        Line::from(""),
+        Line::from("Entries were wiped from memory after being idle."),
+        Line::from("Enter your master password to continue where you left off."),
+    ];
+    if !app.search_query.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from(format!("Search: {}", app.search_query)));
+    }
+
+    let locked = Paragraph::new(lines)
+        .alignment(ratatui::layout::Alignment::Center)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title("Locked"));
+    f.render_widget(locked, area);
+}
+
+fn render_recovery(f: &mut Frame, app: &App, area: Rect) {
+    let error = app
+        .vault
+        .corruption()
+        .map(|corruption| corruption.to_string())
+        .unwrap_or_default();
+    let backup = app
+        .latest_backup
+        .as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "none found".to_string());
+
+    let salvage = match app.vault.salvage_source() {
+        Some(source) => format!("s  Salvage {} readable entries from {} into a new vault", app.salvage_count, source.display()),
+        None => "s  Salvage entries (unavailable: no backup opens with this password)".to_string(),
     };
-    
-    let status = Paragraph::new(status_text)
-        .style(Style::default().fg(Color::White))
-        .block(Block::default().borders(Borders::ALL).title("Status"));
-    f.render_widget(status, chunks[3]);
+
+    let lines = vec![
+        Line::from(Span::styled(error, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))),
+        Line::from(""),
+        Line::from("The vault will not be written until it is recovered."),
+        Line::from(""),
+        Line::from(format!("r  Restore the most recent backup ({})", backup)),
+        Line::from(salvage),
+        Line::from("q  Quit without changing anything"),
+    ];
+
+    let recovery = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title("Recovery"));
+    f.render_widget(recovery, area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlocked_app(test: &str) -> (App, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("passman-tui-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = config::Config { vault_path: dir.join("vault.json"), ..config::Config::default() };
+        let vault = VaultManager::new(&config).unwrap();
+        let mut app = App::with_vault(config, vault);
+        app.vault.unlock("master").unwrap();
+        app.mode = AppMode::Search;
+        (app, dir)
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            app.handle_search_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn every_typed_character_goes_into_the_query() {
+        let (mut app, dir) = unlocked_app("typing");
+        type_text(&mut app, "tag:work -tag:archived name:reddit notes:amazon");
+        app.handle_search_key(KeyEvent::new(KeyCode::Char('Q'), KeyModifiers::SHIFT));
+
+        assert_eq!(app.search_query, "tag:work -tag:archived name:reddit notes:amazonQ");
+        assert!(app.mode == AppMode::Search);
+        assert!(!app.should_quit);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn commands_are_ctrl_chords() {
+        let (mut app, dir) = unlocked_app("chords");
+        app.handle_search_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL));
+        assert!(app.mode == AppMode::Add);
+        assert!(app.search_query.is_empty());
+
+        app.mode = AppMode::Search;
+        app.handle_search_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL));
+        assert!(app.should_quit);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn generated_passwords_are_stored_whole() {
+        let (mut app, dir) = unlocked_app("generate");
+        // Symbols only, so nearly every password would contain the separator
+        app.config.generator = generator::PasswordPolicy {
+            length: 64,
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            ..generator::PasswordPolicy::default()
+        };
+        app.handle_search_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL));
+        app.search_query = "github|".to_string();
+        app.generate_password_input();
+        let generated = app.search_query.split_once('|').unwrap().1.to_string();
+        assert_eq!(generated.len(), 64);
+
+        let input = app.search_query.clone();
+        app.process_input(&input);
+        let entry = app.vault.entries().iter().find(|entry| entry.name == "github").unwrap();
+        assert_eq!(entry.password, generated);
+        assert_eq!(entry.username, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_passphrases_containing_the_field_separator() {
+        let (mut app, dir) = unlocked_app("separator");
+        app.config.passphrase.default = true;
+        app.config.passphrase.separator = "|".to_string();
+        app.mode = AppMode::Add;
+        app.search_query = "github|".to_string();
+        app.generate_password_input();
+        assert_eq!(app.search_query, "github|");
+        assert!(app.status_message.contains("separates the fields"), "{}", app.status_message);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,7 +1,1059 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
 
+use crate::config::Config;
+use crate::crypto::{EncryptedVault, VaultKey};
+use crate::migration::{self, CURRENT_FORMAT_VERSION};
+use crate::otp::OtpSecret;
+use crate::recovery::{self, CorruptVault};
+
+// Every field besides `name` and `password` has a default so vaults written
+// before the rich schema still load.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PasswordEntry {
+    #[serde(default = "new_entry_id")]
+    pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub username: Option<String>,
     pub password: String,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// One-time code generator for accounts using an authenticator app
+    #[serde(default)]
+    pub otp: Option<OtpSecret>,
+    /// Slash separated folder path, e.g. `Work/Servers`, as used by KeePass
+    /// groups and other managers' folders
+    #[serde(default)]
+    pub folder: Option<String>,
+    /// Extra named values kept from imports so they survive a round trip
+    #[serde(default)]
+    pub custom_fields: Vec<CustomField>,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PasswordEntry {
+    pub fn new(name: &str, password: &str) -> Self {
+        let now = Utc::now();
+        Self {
+            id: new_entry_id(),
+            name: name.to_string(),
+            username: None,
+            password: password.to_string(),
+            url: None,
+            tags: Vec::new(),
+            notes: None,
+            otp: None,
+            folder: None,
+            custom_fields: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Overwrites every text field in memory, before the entry is dropped.
+    pub fn wipe(&mut self) {
+        for text in [&mut self.id, &mut self.name, &mut self.password] {
+            text.zeroize();
+        }
+        for text in [&mut self.username, &mut self.url, &mut self.notes, &mut self.folder] {
+            text.zeroize();
+        }
+        self.tags.zeroize();
+        if let Some(otp) = &mut self.otp {
+            otp.secret.zeroize();
+        }
+        for field in &mut self.custom_fields {
+            field.name.zeroize();
+            field.value.zeroize();
+        }
+        self.otp = None;
+        self.custom_fields.clear();
+    }
+
+    /// One-line description: `folder/name (username) - url [tags]`
+    pub fn summary(&self) -> String {
+        let folder = self.folder.as_ref().map(|f| format!("{}/", f)).unwrap_or_default();
+        let username = self.username.as_ref().map(|u| format!(" ({})", u)).unwrap_or_default();
+        let url = self.url.as_ref().map(|u| format!(" - {}", u)).unwrap_or_default();
+        let tags = if !self.tags.is_empty() {
+            format!(" [{}]", self.tags.join(", "))
+        } else {
+            String::new()
+        };
+
+        format!("{}{}{}{}{}", folder, self.name, username, url, tags)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    pub value: String,
+    /// Hidden by default, like a password
+    #[serde(default)]
+    pub protected: bool,
+}
+
+/// Splits a comma separated tag list, dropping empty items.
+pub fn parse_tags(input: &str) -> Vec<String> {
+    input
+        .split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+fn new_entry_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// How many of an entry's most recent uses are kept for frecency ranking.
+pub const MAX_USAGE_EVENTS: usize = 10;
+
+/// One use of an entry, recorded for frecency ranking.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageEvent {
+    pub kind: UsageKind,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageKind {
+    /// The password or one-time code was copied to the clipboard
+    Copy,
+    /// A secret was shown, such as `passman otp` printing a code
+    View,
+}
+
+/// The decrypted contents of the vault file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultDocument {
+    pub format_version: u32,
+    pub entries: Vec<PasswordEntry>,
+    /// Recent uses of each entry by id, oldest first
+    #[serde(default)]
+    pub usage: BTreeMap<String, Vec<UsageEvent>>,
+}
+
+impl Default for VaultDocument {
+    fn default() -> Self {
+        Self {
+            format_version: CURRENT_FORMAT_VERSION,
+            entries: Vec::new(),
+            usage: BTreeMap::new(),
+        }
+    }
+}
+
+impl VaultDocument {
+    fn record_use(&mut self, id: &str, event: UsageEvent) {
+        let events = self.usage.entry(id.to_string()).or_default();
+        events.push(event);
+        if events.len() > MAX_USAGE_EVENTS {
+            events.drain(..events.len() - MAX_USAGE_EVENTS);
+        }
+    }
+}
+
+/// Describes an older vault file that was upgraded while unlocking.
+pub struct Upgrade {
+    pub from_version: u32,
+    pub backup_path: PathBuf,
+}
+
+/// Owns the vault file: loading, querying, mutating and persisting entries.
+///
+/// Both the CLI and the TUI go through this type so they always agree on
+/// where and how the vault is stored.
+#[derive(Clone)]
+pub struct VaultManager {
+    path: PathBuf,
+    document: VaultDocument,
+    key: Option<VaultKey>,
+    recovery: Option<Recovery>,
+    backup_count: usize,
+    /// SHA-256 of the vault file as we last read or wrote it
+    fingerprint: Option<[u8; 32]>,
+    /// Changes made since then, replayed if another process saved meanwhile
+    pending: Vec<Change>,
+    /// Set when `relocate_legacy_vault` moved the legacy vault here
+    relocated_from: Option<PathBuf>,
+}
+
+#[derive(Clone)]
+enum Change {
+    Add(PasswordEntry),
+    Update(PasswordEntry),
+    Delete(String),
+    Use(String, UsageEvent),
+}
+
+impl Change {
+    fn apply(&self, document: &mut VaultDocument) {
+        let entries = &mut document.entries;
+        match self {
+            Change::Add(entry) => {
+                if !entries.iter().any(|e| e.id == entry.id) {
+                    entries.push(entry.clone());
+                }
+            }
+            Change::Update(entry) => match entries.iter_mut().find(|e| e.id == entry.id) {
+                Some(existing) => *existing = entry.clone(),
+                // Deleted by the other process: keep our edit rather than lose it
+                None => entries.push(entry.clone()),
+            },
+            Change::Delete(id) => {
+                entries.retain(|e| &e.id != id);
+                document.usage.remove(id);
+            }
+            Change::Use(id, event) => {
+                if entries.iter().any(|e| &e.id == id) {
+                    document.record_use(id, event.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Kept after a failed parse so the user can salvage entries without
+/// typing the master password again.
+#[derive(Clone)]
+struct Recovery {
+    error: CorruptVault,
+    /// `None` if neither the vault nor any backup opened with the password
+    salvage: Option<Salvage>,
+}
+
+/// Decrypted text to salvage entries from, and the key that decrypted it.
+/// Only text that decrypted is used, so salvaged entries are never
+/// re-encrypted under a password that was never checked.
+#[derive(Clone)]
+struct Salvage {
+    source: PathBuf,
+    content: Zeroizing<String>,
+    key: VaultKey,
+}
+
+impl VaultManager {
+    /// Opens the vault at `config.vault_path`, which callers resolve with
+    /// `Config::with_vault_override`. The vault starts out locked.
+    pub fn new(config: &Config) -> Result<Self> {
+        let path = config.vault_path.clone();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Could not create vault directory {:?}", dir))?;
+        }
+
+        Ok(Self {
+            path,
+            document: VaultDocument::default(),
+            key: None,
+            recovery: None,
+            backup_count: config.backup_count,
+            fingerprint: None,
+            pending: Vec::new(),
+            relocated_from: None,
+        })
+    }
+
+    /// Older versions always kept the vault in `<home>/.passman/vault.json`.
+    /// If that file exists and nothing is at this vault's path yet, move it
+    /// here together with its backups. Only for the vault the config file
+    /// points at, never one chosen with `--vault` or `PASSMAN_VAULT`.
+    pub fn relocate_legacy_vault(&mut self, home: &Path) -> Result<()> {
+        let legacy_path = home.join(".passman").join("vault.json");
+        if self.exists() || !legacy_path.exists() || legacy_path == self.path {
+            return Ok(());
+        }
+
+        let legacy_prefix = "vault.json.";
+        let legacy_dir = legacy_path.parent().unwrap_or(Path::new("."));
+
+        for dir_entry in fs::read_dir(legacy_dir)? {
+            let name = dir_entry?.file_name().to_string_lossy().into_owned();
+            if let Some(suffix) = name.strip_prefix(legacy_prefix) {
+                if suffix != "lock" && suffix != "tmp" {
+                    move_file(&legacy_dir.join(&name), &self.sibling_path(&format!(".{}", suffix)))?;
+                }
+            }
+        }
+        move_file(&legacy_path, &self.path)
+            .with_context(|| format!("Could not move vault from {:?} to {:?}", legacy_path, self.path))?;
+
+        self.relocated_from = Some(legacy_path);
+        Ok(())
+    }
+
+    /// Where `relocate_legacy_vault` moved the vault from, if it did.
+    pub fn relocated_from(&self) -> Option<&Path> {
+        self.relocated_from.as_deref()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Reads the vault and decrypts it with the master password.
+    ///
+    /// A missing file yields an empty vault keyed by `password`. Files in an
+    /// older format (including legacy plaintext vaults) are migrated, backed
+    /// up next to the vault and re-saved in the current encrypted format.
+    pub fn unlock(&mut self, password: &str) -> Result<Option<Upgrade>> {
+        let _lock = self.lock_file()?;
+        self.pending.clear();
+
+        if !self.exists() {
+            self.document = VaultDocument::default();
+            self.key = Some(VaultKey::generate(password)?);
+            self.fingerprint = None;
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("Could not read vault file {:?}", self.path))?;
+        self.fingerprint = Some(fingerprint(content.as_bytes()));
+
+        // A damaged container also fails to parse, and ends up here as if it
+        // were plaintext; either way `password` has not been checked
+        let (text, key, is_plaintext) = match EncryptedVault::parse(&content) {
+            Some(encrypted) => {
+                let (key, plaintext) = encrypted.open(password)?;
+                let text = String::from_utf8(plaintext.to_vec())
+                    .map_err(|_| CorruptVault::new("decrypted contents are not valid UTF-8"))?;
+                (Zeroizing::new(text), key, false)
+            }
+            // Plaintext vault written by an older version
+            None => (Zeroizing::new(content.clone()), VaultKey::generate(password)?, true),
+        };
+
+        let (document, from_version) = match Self::parse_document(&text) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                if let Some(corrupt) = error.downcast_ref::<CorruptVault>() {
+                    let salvage = if is_plaintext {
+                        self.newest_backup_opening_with(password)
+                    } else {
+                        Some(Salvage {
+                            source: self.path.clone(),
+                            content: text,
+                            key,
+                        })
+                    };
+                    self.recovery = Some(Recovery {
+                        error: corrupt.clone(),
+                        salvage,
+                    });
+                }
+                return Err(error);
+            }
+        };
+        self.document = document;
+        self.key = Some(key);
+        self.recovery = None;
+
+        if from_version == CURRENT_FORMAT_VERSION && !is_plaintext {
+            return Ok(None);
+        }
+
+        // Never leave a plaintext copy behind: a legacy plaintext vault is
+        // backed up inside the encrypted container, which still opens here.
+        let backup_path = self.sibling_path(&format!(".v{}.bak", from_version));
+        let backup = match (is_plaintext, self.key.as_ref()) {
+            (true, Some(key)) => serde_json::to_string_pretty(&key.seal(content.as_bytes())?)?,
+            _ => content,
+        };
+        write_private_file(&backup_path, backup.as_bytes())
+            .with_context(|| format!("Could not back up vault to {:?}", backup_path))?;
+
+        // Not rotated: the original file must not become a numbered backup,
+        // least of all when it is plaintext
+        let contents = self.seal_document()?;
+        self.write_atomically(contents.as_bytes())?;
+
+        Ok(Some(Upgrade {
+            from_version,
+            backup_path,
+        }))
+    }
+
+    /// Parses decrypted vault text, migrating older formats. Returns the
+    /// document and the format version it was stored in.
+    fn parse_document(text: &str) -> Result<(VaultDocument, u32)> {
+        let raw: Value = serde_json::from_str(text).map_err(CorruptVault::from)?;
+        let from_version = migration::detect_version(&raw).map_err(|e| CorruptVault::new(e.to_string()))?;
+
+        // Current documents are parsed from the text again so schema errors
+        // come with a line and column
+        let document = if from_version == CURRENT_FORMAT_VERSION {
+            serde_json::from_str(text).map_err(CorruptVault::from)?
+        } else {
+            serde_json::from_value(migration::migrate(raw)?).map_err(CorruptVault::from)?
+        };
+        Ok((document, from_version))
+    }
+
+    /// The parse failure from the last `unlock`, if the vault is corrupt.
+    pub fn corruption(&self) -> Option<&CorruptVault> {
+        self.recovery.as_ref().map(|recovery| &recovery.error)
+    }
+
+    /// The file entries would be salvaged from: the vault if it decrypted,
+    /// otherwise the newest backup that opens with the password.
+    pub fn salvage_source(&self) -> Option<&Path> {
+        self.salvage().map(|salvage| salvage.source.as_path())
+    }
+
+    /// Entries from the salvage source that still parse individually.
+    pub fn salvageable_entries(&self) -> Vec<PasswordEntry> {
+        self.salvage()
+            .map(|salvage| recovery::salvage_entries(&salvage.content))
+            .unwrap_or_default()
+    }
+
+    fn salvage(&self) -> Option<&Salvage> {
+        self.recovery.as_ref().and_then(|recovery| recovery.salvage.as_ref())
+    }
+
+    /// Decrypts the backups, newest first, until one opens with `password`.
+    fn newest_backup_opening_with(&self, password: &str) -> Option<Salvage> {
+        self.backups().ok()?.into_iter().find_map(|backup| {
+            let content = fs::read_to_string(&backup).ok()?;
+            let (key, plaintext) = EncryptedVault::parse(&content)?.open(password).ok()?;
+            let content = Zeroizing::new(String::from_utf8(plaintext.to_vec()).ok()?);
+            Some(Salvage { source: backup, content, key })
+        })
+    }
+
+    /// Replaces the corrupt vault with the entries that could be salvaged.
+    ///
+    /// The damaged file is moved aside rather than overwritten; its new path
+    /// is returned.
+    pub fn recover_salvaged_entries(&mut self) -> Result<PathBuf> {
+        let recovery = self.recovery.as_ref().ok_or_else(|| anyhow!("Vault is not in recovery mode"))?;
+        let salvage = recovery.salvage.clone().ok_or_else(|| {
+            anyhow!("Neither the vault nor any backup opens with this password, so there is nothing to salvage")
+        })?;
+        self.recovery = None;
+
+        let _lock = self.lock_file()?;
+        let entries = recovery::salvage_entries(&salvage.content);
+        let preserved = self.preserve_current("corrupt")?;
+        self.document = VaultDocument {
+            entries,
+            ..VaultDocument::default()
+        };
+        self.key = Some(salvage.key);
+
+        // Written without rotating, so the corrupt file does not end up as
+        // the newest backup generation
+        let contents = self.seal_document()?;
+        self.write_atomically(contents.as_bytes())?;
+        Ok(preserved)
+    }
+
+    /// Backup files next to the vault, newest first.
+    pub fn backups(&self) -> Result<Vec<PathBuf>> {
+        let dir = self.path.parent().ok_or_else(|| anyhow!("Vault path has no parent directory"))?;
+        let prefix = format!("{}.", self.path.file_name().unwrap_or_default().to_string_lossy());
+
+        let mut backups = Vec::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().to_string_lossy().into_owned();
+            let is_backup = !name.ends_with(".tmp") && !name.ends_with(".lock") && !name.contains(".corrupt-");
+            if name.starts_with(&prefix) && is_backup {
+                backups.push((dir_entry.metadata()?.modified()?, dir_entry.path()));
+            }
+        }
+
+        backups.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        Ok(backups.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Replaces the vault with `backup`, keeping the current file next to it
+    /// (as `.corrupt-<time>` or `.pre-restore-<time>`) so the restore can be
+    /// undone; returns where it was kept. The vault is locked afterwards and
+    /// must be unlocked again, since the backup may use another password.
+    pub fn restore_backup(&mut self, backup: &Path) -> Result<Option<PathBuf>> {
+        let _lock = self.lock_file()?;
+        let contents = fs::read(backup)
+            .with_context(|| format!("Could not read backup {:?}", backup))?;
+
+        let label = if self.recovery.is_some() { "corrupt" } else { "pre-restore" };
+        let preserved = if self.exists() {
+            Some(self.preserve_current(label)?)
+        } else {
+            None
+        };
+        self.write_atomically(&contents)?;
+
+        self.recovery = None;
+        self.key = None;
+        self.document = VaultDocument::default();
+        self.pending.clear();
+        Ok(preserved)
+    }
+
+    /// Keeps a copy of the current vault file as `vault.json.<label>-<time>`.
+    fn preserve_current(&self, label: &str) -> Result<PathBuf> {
+        let preserved = self.sibling_path(&format!(".{}-{}", label, Utc::now().format("%Y%m%d%H%M%S")));
+        link_or_copy(&self.path, &preserved)
+            .with_context(|| format!("Could not preserve vault as {:?}", preserved))?;
+        Ok(preserved)
+    }
+
+    /// Forgets the key and wipes the decrypted entries from memory, as if
+    /// the vault had never been unlocked. Changes not yet saved are lost.
+    #[allow(dead_code)] // Only the TUI stays open long enough to lock
+    pub fn lock(&mut self) {
+        for entry in &mut self.document.entries {
+            entry.wipe();
+        }
+        for change in &mut self.pending {
+            match change {
+                Change::Add(entry) | Change::Update(entry) => entry.wipe(),
+                Change::Delete(_) | Change::Use(..) => {}
+            }
+        }
+        self.document = VaultDocument::default();
+        self.pending.clear();
+        // Both hold their secrets in `Zeroizing` buffers, wiped on drop
+        self.key = None;
+        self.recovery = None;
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn entries(&self) -> &[PasswordEntry] {
+        &self.document.entries
+    }
+
+    pub fn usage(&self) -> &BTreeMap<String, Vec<UsageEvent>> {
+        &self.document.usage
+    }
+
+    pub fn get_all_entries(&self) -> Result<Vec<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        Ok(self.document.entries.clone())
+    }
+
+    pub fn add_entry(&mut self, entry: PasswordEntry) -> Result<()> {
+        self.ensure_unlocked()?;
+        if self.document.entries.iter().any(|e| e.id == entry.id) {
+            return Err(anyhow!("Entry with id '{}' already exists", entry.id));
+        }
+        self.pending.push(Change::Add(entry.clone()));
+        self.document.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn update_entry(&mut self, entry: PasswordEntry) -> Result<()> {
+        self.ensure_unlocked()?;
+        let existing = self
+            .document
+            .entries
+            .iter_mut()
+            .find(|e| e.id == entry.id)
+            .ok_or_else(|| anyhow!("Entry '{}' not found", entry.name))?;
+        *existing = PasswordEntry {
+            updated_at: Utc::now(),
+            ..entry
+        };
+        self.pending.push(Change::Update(existing.clone()));
+        Ok(())
+    }
+
+    pub fn delete_entry(&mut self, id: &str) -> Result<()> {
+        self.ensure_unlocked()?;
+        let before = self.document.entries.len();
+        self.document.entries.retain(|e| e.id != id);
+        if self.document.entries.len() == before {
+            return Err(anyhow!("Entry with id '{}' not found", id));
+        }
+        self.document.usage.remove(id);
+        self.pending.push(Change::Delete(id.to_string()));
+        Ok(())
+    }
+
+    /// Records a use of the entry for frecency ranking, saved with the next
+    /// `save_entries`.
+    pub fn record_use(&mut self, id: &str, kind: UsageKind) -> Result<()> {
+        self.ensure_unlocked()?;
+        if !self.document.entries.iter().any(|e| e.id == id) {
+            return Err(anyhow!("Entry with id '{}' not found", id));
+        }
+        let event = UsageEvent { kind, at: Utc::now() };
+        self.document.record_use(id, event.clone());
+        self.pending.push(Change::Use(id.to_string(), event));
+        Ok(())
+    }
+
+    /// Encrypts the entries and writes them to the vault file, rotating the
+    /// previous file into the numbered backups. Saves that only record uses
+    /// don't rotate, so copying passwords can't push real backups out.
+    ///
+    /// If another process saved the vault since we read it, its version is
+    /// loaded and our changes are replayed on top instead of overwriting it.
+    pub fn save_entries(&mut self) -> Result<()> {
+        if let Some(recovery) = &self.recovery {
+            return Err(anyhow!("{}; refusing to overwrite it", recovery.error));
+        }
+
+        let _lock = self.lock_file()?;
+        self.merge_external_changes()?;
+
+        let contents = self.seal_document()?;
+        let usage_only = !self.pending.is_empty() && self.pending.iter().all(|change| matches!(change, Change::Use(..)));
+        if !usage_only {
+            self.rotate_backups()?;
+        }
+        self.write_atomically(contents.as_bytes())?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Picks up entries another process saved since we last read or wrote
+    /// the vault, keeping our unsaved changes on top. Returns whether the
+    /// file had changed.
+    #[allow(dead_code)] // Polled by the TUI only
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        if !self.is_unlocked() || self.recovery.is_some() {
+            return Ok(false);
+        }
+        // Cheap check first, so polling doesn't contend for the lock
+        match fs::read(&self.path) {
+            Ok(content) if Some(fingerprint(&content)) != self.fingerprint => {}
+            _ => return Ok(false),
+        }
+
+        let _lock = self.lock_file()?;
+        self.merge_external_changes()
+    }
+
+    /// Reloads the vault if its file no longer matches what we last read or
+    /// wrote, then replays the pending changes. Must hold the lock. Returns
+    /// whether it reloaded.
+    fn merge_external_changes(&mut self) -> Result<bool> {
+        let content = match fs::read(&self.path) {
+            Ok(content) => content,
+            // Deleted underneath us: our copy gets written back as is
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).with_context(|| format!("Could not read vault file {:?}", self.path)),
+        };
+        let current = fingerprint(&content);
+        if Some(current) == self.fingerprint {
+            return Ok(false);
+        }
+
+        let key = self.key.as_ref().ok_or_else(|| anyhow!("Vault is locked"))?;
+        let encrypted = std::str::from_utf8(&content)
+            .ok()
+            .and_then(EncryptedVault::parse)
+            .ok_or_else(|| anyhow!("Vault file was replaced by another process with an unreadable file"))?;
+        let plaintext = key.open(&encrypted)?;
+        let text = std::str::from_utf8(&plaintext).context("Decrypted vault contents are not valid UTF-8")?;
+        let (mut document, _) = Self::parse_document(text)
+            .context("Vault file changed by another process could not be read")?;
+
+        for change in &self.pending {
+            change.apply(&mut document);
+        }
+        self.document = document;
+        self.fingerprint = Some(current);
+        Ok(true)
+    }
+
+    /// Takes the advisory lock guarding read-modify-write of the vault. It is
+    /// released when the returned file is dropped.
+    fn lock_file(&self) -> Result<File> {
+        let lock_path = self.sibling_path(".lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| format!("Could not open lock file {:?}", lock_path))?;
+        file.lock().context("Could not lock the vault")?;
+        Ok(file)
+    }
+
+    fn seal_document(&self) -> Result<String> {
+        let key = self.key.as_ref().ok_or_else(|| anyhow!("Vault is locked"))?;
+        let plaintext = Zeroizing::new(serde_json::to_vec(&self.document)?);
+        Ok(serde_json::to_string_pretty(&key.seal(&plaintext)?)?)
+    }
+
+    /// Shifts `vault.json.1..N-1` up by one, dropping generation N, and
+    /// keeps the current vault as `vault.json.1`.
+    fn rotate_backups(&self) -> Result<()> {
+        if self.backup_count == 0 || !self.exists() {
+            return Ok(());
+        }
+
+        let oldest = self.generation_path(self.backup_count);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for generation in (1..self.backup_count).rev() {
+            let from = self.generation_path(generation);
+            if from.exists() {
+                fs::rename(&from, self.generation_path(generation + 1))?;
+            }
+        }
+
+        link_or_copy(&self.path, &self.generation_path(1))
+            .context("Could not back up the previous vault")
+    }
+
+    fn generation_path(&self, generation: usize) -> PathBuf {
+        self.sibling_path(&format!(".{}", generation))
+    }
+
+    /// Writes to a temporary file in the vault directory, syncs it and
+    /// renames it over the vault, so a crash leaves either the old or the
+    /// new vault but never a truncated one.
+    fn write_atomically(&mut self, contents: &[u8]) -> Result<()> {
+        let temp_path = self.sibling_path(".tmp");
+
+        let result = (|| -> Result<()> {
+            write_private_file(&temp_path, contents)?;
+            fs::rename(&temp_path, &self.path)?;
+
+            // Persist the rename itself
+            #[cfg(unix)]
+            if let Some(dir) = self.path.parent() {
+                File::open(dir)?.sync_all()?;
+            }
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => {
+                self.fingerprint = Some(fingerprint(contents));
+                Ok(())
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(e.context(format!("Could not write vault file {:?}", self.path)))
+            }
+        }
+    }
+
+    /// A path next to the vault file, e.g. `vault.json.v0.bak` for ".v0.bak".
+    fn sibling_path(&self, suffix: &str) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(suffix);
+        self.path.with_file_name(file_name)
+    }
+
+    pub fn backup_count(&self) -> usize {
+        self.backup_count
+    }
+
+    fn ensure_unlocked(&self) -> Result<()> {
+        if self.is_unlocked() {
+            Ok(())
+        } else {
+            Err(anyhow!("Vault is locked"))
+        }
+    }
+}
+
+fn fingerprint(content: &[u8]) -> [u8; 32] {
+    Sha256::digest(content).into()
+}
+
+/// Creates or truncates `path` readable by the owner only, writes
+/// `contents` and syncs them to disk.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    // The mode only applies to new files; an existing one keeps its own
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Renames `from` to `to`, copying across filesystems when needed.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Hard-links `from` to `to` (cheap, and unaffected by later renames over
+/// `from`), falling back to a copy on filesystems without hard links.
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("passman-vault-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn open(dir: &Path, backup_count: usize) -> VaultManager {
+        let config = Config {
+            vault_path: dir.join("vault.json"),
+            backup_count,
+            ..Config::default()
+        };
+        VaultManager::new(&config).unwrap()
+    }
+
+    fn names(entries: &[PasswordEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    /// A vault holding `github` and `gitlab`, whose previous generation
+    /// `vault.json.1` holds only `github`.
+    fn vault_with_backup(dir: &Path) -> VaultManager {
+        let mut vault = open(dir, 5);
+        vault.unlock("master").unwrap();
+        vault.add_entry(PasswordEntry::new("github", "one")).unwrap();
+        vault.save_entries().unwrap();
+        vault.add_entry(PasswordEntry::new("gitlab", "two")).unwrap();
+        vault.save_entries().unwrap();
+        vault
+    }
+
+    #[test]
+    fn replays_pending_changes_over_an_external_save() {
+        let dir = test_dir("merge");
+        let mut first = open(&dir, 5);
+        first.unlock("master").unwrap();
+        let shared = PasswordEntry::new("shared", "old");
+        first.add_entry(shared.clone()).unwrap();
+        first.save_entries().unwrap();
+
+        let mut second = open(&dir, 5);
+        second.unlock("master").unwrap();
+
+        // Both change the vault without seeing the other's save
+        first.add_entry(PasswordEntry::new("github", "one")).unwrap();
+        first.delete_entry(&shared.id).unwrap();
+        first.save_entries().unwrap();
+        second.add_entry(PasswordEntry::new("gitlab", "two")).unwrap();
+        second.update_entry(PasswordEntry { password: "new".to_string(), ..shared }).unwrap();
+        second.save_entries().unwrap();
+
+        // The second save replayed its changes on top of the first's; the
+        // entry edited after being deleted elsewhere is kept
+        assert_eq!(names(second.entries()), ["github", "gitlab", "shared"]);
+        assert_eq!(second.entries()[2].password, "new");
+
+        assert!(first.reload_if_changed().unwrap());
+        assert_eq!(names(first.entries()), ["github", "gitlab", "shared"]);
+        assert!(!first.reload_if_changed().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lock_file_is_exclusive() {
+        let dir = test_dir("lock");
+        let vault = open(&dir, 5);
+        let held = vault.lock_file().unwrap();
+
+        let other = File::open(dir.join("vault.json.lock")).unwrap();
+        assert!(matches!(other.try_lock(), Err(std::fs::TryLockError::WouldBlock)));
+        drop(held);
+        other.try_lock().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_backups_newest_first_and_keeps_backup_count() {
+        let dir = test_dir("rotate");
+        let mut vault = open(&dir, 2);
+        vault.unlock("master").unwrap();
+        let mut saved = Vec::new();
+        for name in ["one", "two", "three", "four"] {
+            vault.add_entry(PasswordEntry::new(name, "secret")).unwrap();
+            vault.save_entries().unwrap();
+            saved.push(fs::read_to_string(vault.path()).unwrap());
+        }
+
+        // The vault holds the fourth save, .1 the third and .2 the second;
+        // the first has been dropped
+        assert_eq!(fs::read_to_string(dir.join("vault.json.1")).unwrap(), saved[2]);
+        assert_eq!(fs::read_to_string(dir.join("vault.json.2")).unwrap(), saved[1]);
+        assert!(!dir.join("vault.json.3").exists());
+        assert_eq!(vault.backups().unwrap(), [dir.join("vault.json.1"), dir.join("vault.json.2")]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for file in ["vault.json", "vault.json.1", "vault.json.2"] {
+                let mode = fs::metadata(dir.join(file)).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600, "{} is readable by others", file);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_count_zero_keeps_no_backups() {
+        let dir = test_dir("no-backups");
+        let mut vault = open(&dir, 0);
+        vault.unlock("master").unwrap();
+        for name in ["one", "two"] {
+            vault.add_entry(PasswordEntry::new(name, "secret")).unwrap();
+            vault.save_entries().unwrap();
+        }
+        assert!(vault.backups().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn salvages_a_damaged_container_from_the_newest_backup_that_opens() {
+        let dir = test_dir("salvage-backup");
+        let vault = vault_with_backup(&dir);
+        let content = fs::read_to_string(vault.path()).unwrap();
+        let damaged = &content[..content.len() / 2];
+        fs::write(vault.path(), damaged).unwrap();
+
+        // A mistyped password opens no backup, so nothing may be re-keyed
+        // with it
+        let mut mistyped = open(&dir, 5);
+        assert!(mistyped.unlock("mastr").is_err());
+        assert!(mistyped.corruption().is_some());
+        assert_eq!(mistyped.salvage_source(), None);
+        assert!(mistyped.salvageable_entries().is_empty());
+        assert!(mistyped.recover_salvaged_entries().is_err());
+        assert_eq!(fs::read_to_string(dir.join("vault.json")).unwrap(), damaged);
+
+        let mut reopened = open(&dir, 5);
+        assert!(reopened.unlock("master").is_err());
+        assert_eq!(reopened.salvage_source(), Some(dir.join("vault.json.1").as_path()));
+        assert_eq!(names(&reopened.salvageable_entries()), ["github"]);
+        reopened.recover_salvaged_entries().unwrap();
+
+        let mut recovered = open(&dir, 5);
+        recovered.unlock("master").unwrap();
+        assert_eq!(names(recovered.entries()), ["github"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn salvages_a_vault_that_decrypts_but_does_not_parse() {
+        let dir = test_dir("salvage-vault");
+        let entry = serde_json::to_string(&PasswordEntry::new("github", "one")).unwrap();
+        let text = format!(r#"{{"format_version": 5, "entries": [{}, {{"name": "trunc"#, entry);
+        let sealed = VaultKey::generate("master").unwrap().seal(text.as_bytes()).unwrap();
+        fs::write(dir.join("vault.json"), serde_json::to_string(&sealed).unwrap()).unwrap();
+
+        let mut vault = open(&dir, 5);
+        let error = vault.unlock("master").err().unwrap();
+        assert!(error.downcast_ref::<CorruptVault>().is_some());
+        assert_eq!(vault.salvage_source(), Some(dir.join("vault.json").as_path()));
+        assert_eq!(names(&vault.salvageable_entries()), ["github"]);
+
+        let preserved = vault.recover_salvaged_entries().unwrap();
+        assert!(preserved.exists());
+        let mut recovered = open(&dir, 5);
+        recovered.unlock("master").unwrap();
+        assert_eq!(names(recovered.entries()), ["github"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_explicit_vault_path_leaves_the_legacy_vault_alone() {
+        let dir = test_dir("explicit-path");
+        let legacy = dir.join("home").join(".passman");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("vault.json"), "legacy").unwrap();
+
+        let config = Config::default().with_vault_override(Some(&dir.join("vault.json")));
+        assert!(config.vault_overridden);
+        let vault = VaultManager::new(&config).unwrap();
+        assert!(!vault.exists());
+        assert_eq!(vault.relocated_from(), None);
+        assert!(legacy.join("vault.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relocates_the_legacy_vault_and_its_backups() {
+        let dir = test_dir("relocate");
+        let home = dir.join("home");
+        let legacy = home.join(".passman");
+        fs::create_dir_all(&legacy).unwrap();
+        for name in ["vault.json", "vault.json.1", "vault.json.lock"] {
+            fs::write(legacy.join(name), name).unwrap();
+        }
+
+        let mut vault = open(&dir, 5);
+        vault.relocate_legacy_vault(&home).unwrap();
+        assert_eq!(vault.relocated_from(), Some(legacy.join("vault.json").as_path()));
+        assert_eq!(fs::read_to_string(dir.join("vault.json")).unwrap(), "vault.json");
+        assert_eq!(fs::read_to_string(dir.join("vault.json.1")).unwrap(), "vault.json.1");
+        // The lock belongs to whoever holds it, so it stays behind
+        assert!(!legacy.join("vault.json").exists());
+        assert!(legacy.join("vault.json.lock").exists());
+
+        // Once a vault is in place, a legacy one is never moved over it
+        fs::write(legacy.join("vault.json"), "stale").unwrap();
+        let mut reopened = open(&dir, 5);
+        reopened.relocate_legacy_vault(&home).unwrap();
+        assert_eq!(reopened.relocated_from(), None);
+        assert_eq!(fs::read_to_string(dir.join("vault.json")).unwrap(), "vault.json");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_lose_the_permissions_of_the_file_they_replace() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("private-file");
+        let path = dir.join("export.csv");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private_file(&path, b"name,password").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "name,password");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}